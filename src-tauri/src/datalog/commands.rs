@@ -1,8 +1,11 @@
+use tauri::AppHandle;
 use wpilog::log::DatalogEntryResponse;
 
-use crate::{error::{log_result, EnokiError}, DATALOG};
+use crate::{error::{log_result, EnokiError}, DATALOG, NETWORK_CLIENT_MAP};
+use crate::networktable::handler::NetworkTableClientId;
 
 use super::handler::open_datalog;
+use super::replay::{start_replay_client, PlaybackCommand, PlaybackStatus, MAX_REPLAY_SPEED};
 
 
 #[tauri::command]
@@ -15,4 +18,70 @@ pub fn read_datalog(path: String) -> Result<Vec<DatalogEntryResponse>, EnokiErro
 #[tauri::command]
 pub fn retrieve_dl_daemon_data() -> Vec<DatalogEntryResponse> {
     DATALOG.with(|datalog| datalog.borrow_mut().get_all_entries().clone())
-}
\ No newline at end of file
+}
+
+/// Replays the log as a network table client, subscribe to it like any other client.
+/// The log loads in the background, a log that can't be read leaves the client failed
+#[tauri::command]
+pub fn start_datalog_replay(
+    app_handle: AppHandle,
+    path: String,
+) -> Result<NetworkTableClientId, EnokiError> {
+    let id = NetworkTableClientId::new_for_replay(path.clone());
+    if let Some(mut client) = NETWORK_CLIENT_MAP.with(|map| map.borrow_mut().remove(&id)) {
+        tracing::info!("Stopping replay {}", id);
+        client.stop();
+    }
+
+    let client = log_result(start_replay_client(app_handle, id.clone(), path.into()))?;
+    NETWORK_CLIENT_MAP.with(|map| {
+        map.borrow_mut().insert(id.clone(), client);
+    });
+    Ok(id)
+}
+
+fn control_replay(client_id: NetworkTableClientId, command: PlaybackCommand) -> Result<(), EnokiError> {
+    NETWORK_CLIENT_MAP.with(|map| {
+        match map.borrow().get(&client_id).and_then(|client| client.playback()) {
+            Some(playback) => playback.send(command),
+            None => Err(EnokiError::NoClient(client_id.repr())),
+        }
+    })
+}
+
+#[tauri::command]
+pub fn play_datalog_replay(client_id: NetworkTableClientId) -> Result<(), EnokiError> {
+    control_replay(client_id, PlaybackCommand::Play)
+}
+
+#[tauri::command]
+pub fn pause_datalog_replay(client_id: NetworkTableClientId) -> Result<(), EnokiError> {
+    control_replay(client_id, PlaybackCommand::Pause)
+}
+
+/// `position` is in log microseconds
+#[tauri::command]
+pub fn seek_datalog_replay(client_id: NetworkTableClientId, position: u64) -> Result<(), EnokiError> {
+    control_replay(client_id, PlaybackCommand::Seek(position))
+}
+
+#[tauri::command]
+pub fn set_datalog_replay_speed(client_id: NetworkTableClientId, speed: f64) -> Result<(), EnokiError> {
+    if !speed.is_finite() || speed <= 0.0 || speed > MAX_REPLAY_SPEED {
+        return Err(EnokiError::InvalidValue(format!(
+            "replay speed {}, it has to be above 0 and at most {}",
+            speed, MAX_REPLAY_SPEED
+        )));
+    }
+    control_replay(client_id, PlaybackCommand::Speed(speed))
+}
+
+#[tauri::command]
+pub fn get_datalog_replay_status(client_id: NetworkTableClientId) -> Option<PlaybackStatus> {
+    NETWORK_CLIENT_MAP.with(|map| {
+        map.borrow()
+            .get(&client_id)
+            .and_then(|client| client.playback())
+            .map(|playback| playback.status())
+    })
+}
@@ -3,28 +3,30 @@ use std::path::PathBuf;
 use tauri::api::path::document_dir;
 use wpilog::log::{CreateDataLogConfig, DataLog, DataLogDaemon, OpenDataLogConfig};
 
-use crate::{check_if_main_thread, error::EnokiError, mushroom_types::MushroomValue, DATALOG};
+use crate::{
+    check_if_main_thread, error::EnokiError, mushroom_types::MushroomValue, time::MonotonicTime,
+    DATALOG,
+};
 
 static RELATIVE_DIRECTORY: &str = "Enoki/Datalogs";
 
-pub fn setup_directory() -> Result<(), ()> {
+pub fn setup_directory() -> Result<(), EnokiError> {
     if let Some(docu_path) = document_dir() {
         let datalog_dir = docu_path.join(RELATIVE_DIRECTORY);
         if !datalog_dir.exists() {
-            let res = std::fs::create_dir_all(datalog_dir);
-            if res.is_err() {
-                tracing::error!("Failed to create datalog directory: {}", res.unwrap_err());
-                return Err(());
+            if let Err(err) = std::fs::create_dir_all(datalog_dir) {
+                tracing::error!("Failed to create datalog directory: {}", err);
+                return Err(EnokiError::DlIo(err.to_string()));
             }
         }
         Ok(())
     } else {
-        Err(())
+        Err(EnokiError::DlIo(String::from("no documents directory")))
     }
 }
 
 pub fn create_datalog_daemon() -> DataLogDaemon {
-    if let Err(_) = setup_directory() {
+    if setup_directory().is_err() {
         tracing::error!("Failed to setup datalog directory");
         panic!();
     }
@@ -84,16 +86,45 @@ pub fn end_datalog_entry(name: &str) -> Result<(), EnokiError> {
 }
 
 pub fn log_datalog_value(name: &str, value: MushroomValue) -> Result<(), EnokiError> {
+    log_datalog_value_at(name, value, MonotonicTime::now())
+}
+
+/// Every record gets a [`MonotonicTime`] so records from different sources line up
+pub fn log_datalog_value_at(
+    name: &str,
+    value: MushroomValue,
+    timestamp: MonotonicTime,
+) -> Result<(), EnokiError> {
     check_if_main_thread()?;
     DATALOG.with(|datalog| {
-        datalog
-            .borrow()
-            .borrow_sender()
-            .append_to_entry(String::from(name), value.into())
+        datalog.borrow().borrow_sender().append_to_entry_with_timestamp(
+            String::from(name),
+            value.into(),
+            timestamp.0,
+        )
     })?;
     Ok(())
 }
 
+/// The datalog entry type of a value, decoded values are logged as the bytes they came from
+pub fn datalog_type(value: &MushroomValue) -> &'static str {
+    match value {
+        MushroomValue::Boolean(_) => "boolean",
+        MushroomValue::Int(_) => "int64",
+        MushroomValue::Float(_) => "float",
+        MushroomValue::Double(_) => "double",
+        MushroomValue::String(_) => "string",
+        MushroomValue::BooleanArray(_) => "boolean[]",
+        MushroomValue::IntArray(_) => "int64[]",
+        MushroomValue::FloatArray(_) => "float[]",
+        MushroomValue::DoubleArray(_) => "double[]",
+        MushroomValue::StringArray(_) => "string[]",
+        MushroomValue::ByteArray(_) | MushroomValue::Protobuf(_) | MushroomValue::Struct(_) => {
+            "raw"
+        }
+    }
+}
+
 pub fn open_datalog(path: PathBuf) -> Result<DataLog, EnokiError> {
     let config = OpenDataLogConfig {
         file_path: path,
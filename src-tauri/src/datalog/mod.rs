@@ -1,4 +1,5 @@
 
 #[macro_use]
 pub mod commands;
-pub mod handler;
\ No newline at end of file
+pub mod handler;
+pub mod replay;
\ No newline at end of file
@@ -0,0 +1,457 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tauri::{AppHandle, Manager};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::task::JoinHandle as TokioJoinHandle;
+
+use crate::datalog::handler::open_datalog;
+use crate::error::EnokiError;
+use crate::mushroom_types::{MushroomEntry, MushroomPath, MushroomTable};
+use crate::networktable::connection::ConnectionState;
+use crate::networktable::handler::{
+    start_client_task, ClientChannels, ClientTrackers, NetworkTableClient,
+    NetworkTableClientConfig, NetworkTableClientId, NetworkTableUpdate, SubscriptionPackage,
+    SubscriptionRequest, NT_UPDATE_EVENT,
+};
+use crate::networktable::history::TopicHistory;
+use crate::networktable::topics::TopicInfo;
+use crate::schema::{expand_entry, SchemaRegistry};
+use crate::time::ServerTime;
+use crate::{check_if_main_thread, THREAD_POOL};
+
+/// The fastest a replay can be played, faster jumps through the log more than it plays it
+pub const MAX_REPLAY_SPEED: f64 = 1000.0;
+
+/// How often a playing replay sends the values it passed when coalescing is off
+const MIN_FRAME: Duration = Duration::from_millis(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize)]
+pub struct PlaybackStatus {
+    pub playing: bool,
+    pub speed: f64,
+    /// Log microseconds, like `start` and `end`
+    pub position: u64,
+    pub start: u64,
+    pub end: u64,
+}
+
+#[derive(Debug)]
+pub enum PlaybackCommand {
+    Play,
+    Pause,
+    /// Log microseconds, clamped to the log
+    Seek(u64),
+    Speed(f64),
+}
+
+/// The client side of a replay, every clone controls the same replay
+#[derive(Debug, Clone)]
+pub struct PlaybackControl {
+    sender: UnboundedSender<PlaybackCommand>,
+    status: Arc<Mutex<PlaybackStatus>>,
+}
+
+impl PlaybackControl {
+    pub fn send(&self, command: PlaybackCommand) -> Result<(), EnokiError> {
+        self.sender
+            .send(command)
+            .map_err(|_| EnokiError::Replay(String::from("the replay has stopped")))
+    }
+
+    pub fn status(&self) -> PlaybackStatus {
+        *self.status.lock().unwrap()
+    }
+}
+
+/// NetworkTables uses other names for the 64 bit integer types
+fn topic_type(entry_type: &str) -> String {
+    match entry_type {
+        "int64" => String::from("int"),
+        "int64[]" => String::from("int[]"),
+        other => String::from(other),
+    }
+}
+
+/// The values and topics of a log, sorted for playing
+struct LoadedLog {
+    samples: Vec<Sample>,
+    topics: Vec<TopicInfo>,
+    start: u64,
+    end: u64,
+}
+
+/// Reads the whole log and decodes its structs, takes a while for long logs
+fn load_datalog(path: PathBuf) -> Result<LoadedLog, EnokiError> {
+    let mut records = open_datalog(path)?.get_all_entries();
+    records.sort_by_key(|record| record.timestamp);
+    let start = records.first().map_or(0, |record| record.timestamp);
+    let end = records.last().map_or(0, |record| record.timestamp);
+
+    let mut topics: HashMap<String, TopicInfo> = HashMap::new();
+    for record in &records {
+        topics.entry(record.name.clone()).or_insert_with(|| {
+            TopicInfo::new(
+                record.name.clone(),
+                record.id as i32,
+                topic_type(&record.entry_type),
+            )
+        });
+    }
+    //schemas are logged before the values that need them
+    let mut schemas = SchemaRegistry::new();
+    let samples: Vec<Sample> = records
+        .into_iter()
+        .flat_map(|record| {
+            let entry = MushroomEntry::new(
+                record.value.into(),
+                record.name.as_str().into(),
+                Some(ServerTime(record.timestamp)),
+            );
+            let topic_type = topic_type(&record.entry_type);
+            expand_entry(&mut schemas, entry, Some(&topic_type))
+                .into_iter()
+                .map(move |entry| Sample {
+                    topic: record.name.clone(),
+                    entry,
+                })
+        })
+        .collect();
+
+    Ok(LoadedLog {
+        samples,
+        topics: topics.into_values().collect(),
+        start,
+        end,
+    })
+}
+
+/// Starts a client that plays the datalog at `path` instead of connecting to a server,
+/// it is connecting while the log loads and then starts paused at the beginning of the log
+pub fn start_replay_client(
+    app_handle: AppHandle,
+    id: NetworkTableClientId,
+    path: PathBuf,
+) -> Result<NetworkTableClient, EnokiError> {
+    check_if_main_thread()?;
+
+    let (sender, commands) = unbounded_channel();
+    let playback = PlaybackControl {
+        sender,
+        status: Arc::new(Mutex::new(PlaybackStatus {
+            playing: false,
+            speed: 1.0,
+            position: 0,
+            start: 0,
+            end: 0,
+        })),
+    };
+    tracing::info!("Replaying {} as {}", path.display(), id);
+
+    let config = NetworkTableClientConfig {
+        id: id.clone(),
+        policy: None,
+    };
+    Ok(start_client_task(
+        app_handle.clone(),
+        config,
+        Some(playback.clone()),
+        move |trackers, channels| {
+            trackers.connection.set_address(path.display().to_string());
+            let update_id = id.clone();
+            let send_update = move |update| {
+                app_handle
+                    .emit_all(NT_UPDATE_EVENT, update)
+                    .unwrap_or_else(|err| {
+                        tracing::error!("Failed to emit update for {} because {}", update_id, err);
+                    });
+            };
+            replay(
+                id,
+                path,
+                playback.status,
+                send_update,
+                trackers,
+                channels,
+                commands,
+            )
+        },
+    ))
+}
+
+/// A value of the log, struct values are followed by a sample per field
+#[derive(Debug, Clone)]
+pub struct Sample {
+    /// The topic the value was logged under
+    pub topic: String,
+    pub entry: MushroomEntry,
+}
+
+/// Plays the samples of a log, the updates it passes go to `send_update`
+pub struct ReplayState {
+    id: NetworkTableClientId,
+    send_update: Box<dyn FnMut(NetworkTableUpdate) + Send>,
+    history: TopicHistory,
+    /// Every value of the log, oldest first
+    samples: Vec<Sample>,
+    /// Index of the first sample after the position
+    next: usize,
+    status: Arc<Mutex<PlaybackStatus>>,
+    /// When the position last moved while playing
+    clock: Option<Instant>,
+    subscriptions: Vec<SubscriptionPackage>,
+    table: MushroomTable,
+}
+
+impl ReplayState {
+    /// Starts paused at the position of `status`, `samples` have to be sorted by time
+    pub fn new(
+        id: NetworkTableClientId,
+        history: TopicHistory,
+        samples: Vec<Sample>,
+        status: Arc<Mutex<PlaybackStatus>>,
+        send_update: impl FnMut(NetworkTableUpdate) + Send + 'static,
+    ) -> Self {
+        let position = status.lock().unwrap().position;
+        Self {
+            id,
+            send_update: Box::new(send_update),
+            history,
+            samples,
+            next: 0,
+            status,
+            clock: None,
+            subscriptions: Vec::new(),
+            table: MushroomTable::new(ServerTime(position)),
+        }
+    }
+
+    pub fn status(&self) -> PlaybackStatus {
+        *self.status.lock().unwrap()
+    }
+
+    fn covered(&self, sample: &Sample) -> bool {
+        self.subscriptions
+            .iter()
+            .any(|sub| sub.covers(&sample.topic))
+    }
+
+    pub fn command(&mut self, command: PlaybackCommand) {
+        match command {
+            PlaybackCommand::Play => {
+                let mut status = self.status.lock().unwrap();
+                //playing a finished replay starts it over
+                if status.position >= status.end {
+                    drop(status);
+                    let start = self.status().start;
+                    self.seek(start);
+                    status = self.status.lock().unwrap();
+                }
+                status.playing = true;
+                self.clock = Some(Instant::now());
+            }
+            PlaybackCommand::Pause => {
+                self.advance();
+                self.status.lock().unwrap().playing = false;
+                self.clock = None;
+            }
+            PlaybackCommand::Seek(position) => self.seek(position),
+            PlaybackCommand::Speed(speed) => {
+                //the time played so far counts at the old speed
+                self.advance();
+                self.status.lock().unwrap().speed = speed;
+            }
+        }
+    }
+
+    pub fn subscription(&mut self, request: SubscriptionRequest) {
+        match request {
+            SubscriptionRequest::Subscribe(packages) => {
+                for package in packages {
+                    self.subscriptions
+                        .retain(|sub| sub.name() != package.name());
+                    self.subscriptions.push(package);
+                }
+            }
+            SubscriptionRequest::Unsubscribe(names) => {
+                self.subscriptions
+                    .retain(|sub| !names.iter().any(|name| name == sub.name()));
+            }
+        }
+        //rebuilding the table picks up new topics and drops unsubscribed ones
+        let position = self.status().position;
+        self.seek(position);
+    }
+
+    /// Moves the position by the time that passed since the last call while playing
+    fn advance(&mut self) {
+        let now = Instant::now();
+        if let Some(clock) = self.clock.replace(now) {
+            self.play(now - clock);
+        }
+    }
+
+    /// Moves the position by `elapsed` at the playback speed, only while playing
+    pub fn play(&mut self, elapsed: Duration) {
+        if self.clock.is_none() {
+            return;
+        }
+        let (position, end) = {
+            let mut status = self.status.lock().unwrap();
+            let played = (elapsed.as_micros() as f64 * status.speed) as u64;
+            status.position = status.position.saturating_add(played).min(status.end);
+            if status.position >= status.end {
+                status.playing = false;
+            }
+            (status.position, status.end)
+        };
+        if position >= end {
+            self.clock = None;
+        }
+
+        let mut updates = MushroomTable::new(ServerTime(position));
+        while let Some(sample) = self.samples.get(self.next) {
+            if sample.entry.get_timestamp().unwrap_or_default() > ServerTime(position) {
+                break;
+            }
+            if self.covered(sample) {
+                self.history.record(&sample.entry);
+                updates.add_entry(sample.entry.clone());
+            }
+            self.next += 1;
+        }
+        self.table.update_all(&updates);
+        self.emit(updates, Vec::new());
+    }
+
+    /// Rebuilds the table as it was at `position`
+    fn seek(&mut self, position: u64) {
+        let position = {
+            let mut status = self.status.lock().unwrap();
+            status.position = position.clamp(status.start, status.end);
+            status.position
+        };
+        if self.clock.is_some() {
+            self.clock = Some(Instant::now());
+        }
+        let mut table = MushroomTable::new(ServerTime(position));
+        self.next = 0;
+        while let Some(sample) = self.samples.get(self.next) {
+            if sample.entry.get_timestamp().unwrap_or_default() > ServerTime(position) {
+                break;
+            }
+            if self.covered(sample) {
+                table.add_entry(sample.entry.clone());
+            }
+            self.next += 1;
+        }
+        let removed: Vec<MushroomPath> = self
+            .table
+            .get_entries()
+            .iter()
+            .map(MushroomEntry::get_path)
+            .filter(|path| !table.has_entry(path))
+            .collect();
+        //keep the table so its versions carry on for deltas
+        for path in &removed {
+            self.table.remove_entry(path);
+        }
+        self.table.update_all(&table);
+        self.emit(table, removed);
+    }
+
+    fn emit(&mut self, entries: MushroomTable, removed: Vec<MushroomPath>) {
+        if !entries.is_empty() || !removed.is_empty() {
+            (self.send_update)(NetworkTableUpdate::new(self.id.clone(), entries, removed));
+        }
+    }
+}
+
+fn replay(
+    id: NetworkTableClientId,
+    path: PathBuf,
+    status: Arc<Mutex<PlaybackStatus>>,
+    send_update: impl FnMut(NetworkTableUpdate) + Send + 'static,
+    trackers: ClientTrackers,
+    channels: ClientChannels,
+    mut commands: UnboundedReceiver<PlaybackCommand>,
+) -> TokioJoinHandle<()> {
+    let ClientChannels {
+        mut subscriptions,
+        mut input,
+        output,
+        mut shutdown,
+        coalesce,
+        ..
+    } = channels;
+    //error handling is in the thread
+    THREAD_POOL.with(|thread_pool| {
+        thread_pool.borrow().as_ref().unwrap().spawn(async move {
+            trackers.connection.transition(ConnectionState::Connecting);
+            //commands and subscriptions wait in their channels until the log is loaded
+            let log = tokio::select! {
+                _ = shutdown.changed() => {
+                    trackers.connection.transition(ConnectionState::Stopped);
+                    return;
+                }
+                log = tokio::task::spawn_blocking(move || load_datalog(path)) => log
+                    .map_err(|err| EnokiError::Replay(err.to_string()))
+                    .and_then(|log| log),
+            };
+            let log = match log {
+                Ok(log) => log,
+                Err(err) => {
+                    trackers.connection.report_error(err);
+                    trackers.connection.transition(ConnectionState::Failed);
+                    return;
+                }
+            };
+            {
+                let mut status = status.lock().unwrap();
+                status.start = log.start;
+                status.end = log.end;
+                status.position = log.start;
+            }
+            for info in log.topics {
+                trackers.topics.announce_info(info);
+            }
+            let mut state = ReplayState::new(
+                id,
+                trackers.history.clone(),
+                log.samples,
+                status,
+                send_update,
+            );
+            trackers.connection.transition(ConnectionState::Connected);
+            loop {
+                let frame = (*coalesce.borrow()).max(MIN_FRAME);
+                tokio::select! {
+                    _ = shutdown.changed() => break,
+                    command = commands.recv() => match command {
+                        Some(command) => state.command(command),
+                        None => break,
+                    },
+                    request = subscriptions.recv() => match request {
+                        Some(request) => state.subscription(request),
+                        None => break,
+                    },
+                    request = input.recv() => match request {
+                        Some(_) => trackers.connection.report_error(EnokiError::Replay(
+                            String::from("replays can't be published to"),
+                        )),
+                        None => break,
+                    },
+                    _ = tokio::time::sleep(frame), if state.clock.is_some() => state.advance(),
+                }
+                output.update(state.table.clone()).unwrap_or_else(|err| {
+                    tracing::error!("Failed to send to network table client {}", state.id);
+                    tracing::error!("Error: {}", err);
+                });
+            }
+            trackers.connection.transition(ConnectionState::Stopped);
+            tracing::info!("Replay {} stopped", state.id);
+        })
+    })
+}
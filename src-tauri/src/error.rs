@@ -12,28 +12,38 @@ pub enum EnokiError {
     NTTimeout(#[from] network_tables::NetworkTablesError),
     #[error("Not main thread: {0:?}")]
     NotMainThread(String),
+    #[error("NT connect error: {0:?}")]
+    NTConnect(String),
+    #[error("NT subscribe error: {0:?}")]
+    NTSubscribe(String),
+    #[error("NT publish error: {0:?}")]
+    NTPublish(String),
+    #[error("NT decode error: {0:?}")]
+    NTDecode(String),
+    #[error("No network table client: {0:?}")]
+    NoClient(String),
+    #[error("Invalid value: {0:?}")]
+    InvalidValue(String),
+    #[error("NT server error: {0:?}")]
+    NTServer(String),
+    #[error("Replay error: {0:?}")]
+    Replay(String),
 }
 
 #[inline(always)]
 /// Logs the error if there is one
 pub fn log_result<T, E: std::error::Error>(result: Result<T, E>) -> Result<T, E> {
-    match &result {
-        Err(err) => {
-            tracing::error!("{}", err)
-        }
-        _ => {}
-    };
+    if let Err(err) = &result {
+        tracing::error!("{}", err)
+    }
     result
 }
 
 #[inline(always)]
 /// Consumes the result and logs the error if there is one
 pub fn log_result_consume<T, E: std::error::Error>(result: Result<T, E>) {
-    match &result {
-        Err(err) => {
-            tracing::error!("{}", err)
-        }
-        _ => {}
+    if let Err(err) = &result {
+        tracing::error!("{}", err)
     }
 }
 
@@ -6,6 +6,7 @@ use mushroom_types::MushroomValue;
 use networktable::handler::{
     get_connect_client_names, NetworkTableClient, NetworkTableClientId,
 };
+use networktable::server::NetworkTableServer;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::thread;
@@ -16,6 +17,9 @@ use wpilog::log::DataLogDaemon;
 
 use crate::datalog::handler::{create_datalog_daemon, log_datalog_value, start_datalog_entry};
 use crate::error::log_result_consume;
+use crate::networktable::ipc::{handle_ipc_request, IPC_SCHEME};
+use crate::networktable::metrics::log_client_metrics;
+use crate::networktable::recorder::write_recordings;
 
 use crate::datalog::commands::*;
 use crate::networktable::commands::*;
@@ -23,12 +27,14 @@ use crate::networktable::commands::*;
 
 mod error;
 pub mod mushroom_types;
+pub mod time;
 
 #[cfg(test)]
 mod test;
 
 #[macro_use]
 pub mod datalog;
+pub mod schema;
 pub mod networktable;
 
 thread_local! {
@@ -42,14 +48,15 @@ thread_local! {
 
     static NETWORK_CLIENT_MAP: RefCell<HashMap<NetworkTableClientId, NetworkTableClient>> = RefCell::new(HashMap::new());
 
+    static NETWORK_SERVER: RefCell<Option<NetworkTableServer>> = const { RefCell::new(None) };
+
     static DATALOG: RefCell<DataLogDaemon> = RefCell::new(create_datalog_daemon());
 }
 
 #[tokio::main]
 async fn main() {
     // guard lock needs to live till end of program
-    let _guard_lock;
-    if cfg!(debug_assertions) {
+    let _guard_lock = if cfg!(debug_assertions) {
         let (non_blocking_std_io, _guard_std_io) =
             tracing_appender::non_blocking(std::io::stdout());
         tracing_subscriber::fmt()
@@ -61,7 +68,7 @@ async fn main() {
             .with_level(true)
             .with_writer(non_blocking_std_io)
             .init();
-        _guard_lock = _guard_std_io;
+        _guard_std_io
     } else {
         let (non_blocking_file, _guard_file) = tracing_appender::non_blocking(TraceWriter::new());
         tracing_subscriber::fmt()
@@ -72,32 +79,51 @@ async fn main() {
             .with_max_level(LevelFilter::WARN)
             .with_writer(non_blocking_file)
             .init();
-        _guard_lock = _guard_file;
-    }
+        _guard_file
+    };
 
     tauri::Builder::default()
         .plugin(backend_plugin())
         .invoke_handler(tauri::generate_handler![
             start_network_table_client,
+            start_network_table_client_for_team,
             stop_network_table_client,
             does_network_table_client_exist,
+            get_network_table_client_state,
+            get_network_table_client_config,
+            get_network_table_client_metrics,
             subscribe_to_topic,
-            set_boolean_topic,
-            set_float_topic,
-            set_double_topic,
-            set_string_topic,
-            set_int_topic,
-            set_boolean_array_topic,
-            set_float_array_topic,
-            set_double_array_topic,
-            set_string_array_topic,
-            set_int_array_topic,
+            unsubscribe_from_topic,
+            list_subscriptions,
+            publish_to_topics,
+            set_topic_properties,
+            unpublish_topics,
+            list_topics,
+            get_topic_history,
+            set_history_config,
+            set_update_coalescing,
+            set_network_table_recording,
+            start_network_table_server,
+            stop_network_table_server,
+            get_network_table_server_clients,
             get_subbed_entries_values,
+            get_subbed_entries_delta,
             get_client_timestamp,
             get_subbed_entry_value,
+            get_subbed_entries_under,
+            get_subbed_entries_matching,
+            get_subbed_entry_children,
+            get_subbed_entries_tree,
             retrieve_dl_daemon_data,
-            read_datalog
+            read_datalog,
+            start_datalog_replay,
+            play_datalog_replay,
+            pause_datalog_replay,
+            seek_datalog_replay,
+            set_datalog_replay_speed,
+            get_datalog_replay_status
         ])
+        .register_uri_scheme_protocol(IPC_SCHEME, handle_ipc_request)
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
@@ -127,11 +153,14 @@ fn per_frame() {
         "/ClientsConnected",
         MushroomValue::StringArray(get_connect_client_names()),
     ));
+    write_recordings();
+    log_client_metrics();
 }
 
 ///called when the ui first starts up
 fn init() {
     tracing::info!("Init");
+    time::init();
     log_result_consume(start_datalog_entry(
         "/ClientsConnected",
         "string[]",
@@ -1,17 +1,20 @@
-use std::{collections::HashMap, fmt::Display, hash::Hash, time::Instant};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Display,
+    hash::Hash,
+    sync::atomic::{AtomicU64, Ordering},
+};
 
 use serde::{
+    de::{IgnoredAny, MapAccess, Unexpected, Visitor},
     ser::{SerializeMap, SerializeSeq},
     Deserialize, Serialize,
 };
+use serde_bytes::ByteBuf;
 use wpilog::log::DataLogValue;
 
-/// Microseconds
-type MushroomTimeStamp = u128;
-
-pub fn now() -> MushroomTimeStamp {
-    Instant::now().elapsed().as_micros()
-}
+use crate::schema::StructValue;
+use crate::time::ServerTime;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum MushroomValue {
@@ -27,6 +30,7 @@ pub enum MushroomValue {
     StringArray(Vec<String>),
     Boolean(bool),
     BooleanArray(Vec<bool>),
+    Struct(StructValue),
 }
 
 impl Serialize for MushroomValue {
@@ -38,37 +42,39 @@ impl Serialize for MushroomValue {
             MushroomValue::ByteArray(v) => {
                 let mut map = serializer.serialize_map(Some(2))?;
                 map.serialize_entry("type", "ByteArray")?;
-                map.serialize_entry("value", v)?;
+                //a bin blob in msgpack instead of an array of numbers
+                map.serialize_entry("value", serde_bytes::Bytes::new(v))?;
                 map.end()
             }
             MushroomValue::Protobuf(v) => {
                 let mut map = serializer.serialize_map(Some(2))?;
                 map.serialize_entry("type", "Protobuf")?;
-                map.serialize_entry("value", v)?;
+                //a bin blob in msgpack instead of an array of numbers
+                map.serialize_entry("value", serde_bytes::Bytes::new(v))?;
                 map.end()
             }
             MushroomValue::Float(v) => {
                 let mut map = serializer.serialize_map(Some(2))?;
                 map.serialize_entry("type", "Float")?;
-                map.serialize_entry("value", v)?;
+                map.serialize_entry("value", &LosslessFloat(*v))?;
                 map.end()
             }
             MushroomValue::FloatArray(v) => {
                 let mut map = serializer.serialize_map(Some(2))?;
                 map.serialize_entry("type", "FloatArray")?;
-                map.serialize_entry("value", v)?;
+                map.serialize_entry("value", &LosslessFloats(v))?;
                 map.end()
             }
             MushroomValue::Double(v) => {
                 let mut map = serializer.serialize_map(Some(2))?;
                 map.serialize_entry("type", "Double")?;
-                map.serialize_entry("value", v)?;
+                map.serialize_entry("value", &LosslessFloat(*v))?;
                 map.end()
             }
             MushroomValue::DoubleArray(v) => {
                 let mut map = serializer.serialize_map(Some(2))?;
                 map.serialize_entry("type", "DoubleArray")?;
-                map.serialize_entry("value", v)?;
+                map.serialize_entry("value", &LosslessFloats(v))?;
                 map.end()
             }
             MushroomValue::Int(v) => {
@@ -107,7 +113,198 @@ impl Serialize for MushroomValue {
                 map.serialize_entry("value", v)?;
                 map.end()
             }
+            MushroomValue::Struct(v) => {
+                let mut map = serializer.serialize_map(Some(4))?;
+                map.serialize_entry("type", "Struct")?;
+                map.serialize_entry("struct_type", &v.type_name)?;
+                map.serialize_entry("value", &v.fields)?;
+                map.serialize_entry("raw", serde_bytes::Bytes::new(&v.raw))?;
+                map.end()
+            }
+        }
+    }
+}
+
+/// A float that survives formats like json that have no numbers for NaN and the infinities,
+/// they are written as the strings javascript uses for them
+struct LosslessFloat(f64);
+
+impl Serialize for LosslessFloat {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        if self.0.is_finite() || !serializer.is_human_readable() {
+            serializer.serialize_f64(self.0)
+        } else if self.0.is_nan() {
+            serializer.serialize_str("NaN")
+        } else if self.0 > 0.0 {
+            serializer.serialize_str("Infinity")
+        } else {
+            serializer.serialize_str("-Infinity")
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for LosslessFloat {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct FloatVisitor;
+
+        impl<'de> Visitor<'de> for FloatVisitor {
+            type Value = LosslessFloat;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                write!(f, "a number, NaN, Infinity or -Infinity")
+            }
+
+            fn visit_f64<E: serde::de::Error>(self, v: f64) -> Result<Self::Value, E> {
+                Ok(LosslessFloat(v))
+            }
+
+            fn visit_i64<E: serde::de::Error>(self, v: i64) -> Result<Self::Value, E> {
+                Ok(LosslessFloat(v as f64))
+            }
+
+            fn visit_u64<E: serde::de::Error>(self, v: u64) -> Result<Self::Value, E> {
+                Ok(LosslessFloat(v as f64))
+            }
+
+            fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                match v {
+                    "NaN" => Ok(LosslessFloat(f64::NAN)),
+                    "Infinity" => Ok(LosslessFloat(f64::INFINITY)),
+                    "-Infinity" => Ok(LosslessFloat(f64::NEG_INFINITY)),
+                    _ => Err(E::invalid_value(Unexpected::Str(v), &self)),
+                }
+            }
         }
+
+        deserializer.deserialize_any(FloatVisitor)
+    }
+}
+
+struct LosslessFloats<'a>(&'a [f64]);
+
+impl Serialize for LosslessFloats<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_seq(self.0.iter().map(|v| LosslessFloat(*v)))
+    }
+}
+
+fn lossless_floats(floats: Vec<LosslessFloat>) -> Vec<f64> {
+    floats.into_iter().map(|float| float.0).collect()
+}
+
+const VALUE_TYPES: &[&str] = &[
+    "ByteArray",
+    "Protobuf",
+    "Float",
+    "FloatArray",
+    "Double",
+    "DoubleArray",
+    "Int",
+    "IntArray",
+    "String",
+    "StringArray",
+    "Boolean",
+    "BooleanArray",
+    "Struct",
+];
+
+/// Reads the `{type, value}` map a [`MushroomValue`] serializes to,
+/// the value is read straight into the type named before it
+struct MushroomValueVisitor;
+
+impl<'de> Visitor<'de> for MushroomValueVisitor {
+    type Value = MushroomValue;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "a map with the type and value of a value")
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let mut value_type: Option<String> = None;
+        let mut value = None;
+        //structs also carry their type name and the bytes they were decoded from
+        let mut fields: Option<serde_json::Value> = None;
+        let mut struct_type: Option<String> = None;
+        let mut raw: Option<Vec<u8>> = None;
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "type" => value_type = Some(map.next_value()?),
+                "value" => {
+                    value = Some(match value_type.as_deref() {
+                        Some("ByteArray") => {
+                            MushroomValue::ByteArray(map.next_value::<ByteBuf>()?.into_vec())
+                        }
+                        Some("Protobuf") => {
+                            MushroomValue::Protobuf(map.next_value::<ByteBuf>()?.into_vec())
+                        }
+                        Some("Float") => MushroomValue::Float(map.next_value::<LosslessFloat>()?.0),
+                        Some("FloatArray") => {
+                            MushroomValue::FloatArray(lossless_floats(map.next_value()?))
+                        }
+                        Some("Double") => {
+                            MushroomValue::Double(map.next_value::<LosslessFloat>()?.0)
+                        }
+                        Some("DoubleArray") => {
+                            MushroomValue::DoubleArray(lossless_floats(map.next_value()?))
+                        }
+                        Some("Int") => MushroomValue::Int(map.next_value()?),
+                        Some("IntArray") => MushroomValue::IntArray(map.next_value()?),
+                        Some("String") => MushroomValue::String(map.next_value()?),
+                        Some("StringArray") => MushroomValue::StringArray(map.next_value()?),
+                        Some("Boolean") => MushroomValue::Boolean(map.next_value()?),
+                        Some("BooleanArray") => MushroomValue::BooleanArray(map.next_value()?),
+                        Some("Struct") => {
+                            fields = Some(map.next_value()?);
+                            continue;
+                        }
+                        Some(other) => {
+                            return Err(serde::de::Error::unknown_variant(other, VALUE_TYPES))
+                        }
+                        None => {
+                            return Err(serde::de::Error::custom(
+                                "the type of a value has to come before it",
+                            ))
+                        }
+                    })
+                }
+                "struct_type" => struct_type = Some(map.next_value()?),
+                "raw" => raw = Some(map.next_value::<ByteBuf>()?.into_vec()),
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        match value_type.as_deref() {
+            None => Err(serde::de::Error::missing_field("type")),
+            Some("Struct") => Ok(MushroomValue::Struct(StructValue {
+                type_name: struct_type
+                    .ok_or_else(|| serde::de::Error::missing_field("struct_type"))?,
+                fields: fields.ok_or_else(|| serde::de::Error::missing_field("value"))?,
+                raw: raw.ok_or_else(|| serde::de::Error::missing_field("raw"))?,
+            })),
+            Some(_) => value.ok_or_else(|| serde::de::Error::missing_field("value")),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for MushroomValue {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_map(MushroomValueVisitor)
     }
 }
 
@@ -126,69 +323,64 @@ impl Display for MushroomValue {
             MushroomValue::StringArray(v) => write!(f, "StringArray({:?})", v),
             MushroomValue::Boolean(v) => write!(f, "Boolean({:?})", v),
             MushroomValue::BooleanArray(v) => write!(f, "BooleanArray({:?})", v),
+            MushroomValue::Struct(v) => write!(f, "Struct({}, {})", v.type_name, v.fields),
         }
     }
 }
 
 impl MushroomValue {
+    /// Builds a value from the type name it serializes with and a json value of that type
+    pub fn from_json(type_name: &str, value: serde_json::Value) -> Result<MushroomValue, String> {
+        serde_json::from_value(serde_json::json!({ "type": type_name, "value": value }))
+            .map_err(|err| err.to_string())
+    }
+
     pub fn is_binary(&self) -> bool {
-        match self {
-            MushroomValue::ByteArray(_) => true,
-            MushroomValue::Protobuf(_) => true,
-            _ => false,
-        }
+        matches!(self, MushroomValue::ByteArray(_) | MushroomValue::Protobuf(_))
     }
 
     pub fn is_numeric(&self) -> bool {
-        match self {
-            MushroomValue::Float(_) => true,
-            MushroomValue::FloatArray(_) => true,
-            MushroomValue::Double(_) => true,
-            MushroomValue::DoubleArray(_) => true,
-            MushroomValue::Int(_) => true,
-            MushroomValue::IntArray(_) => true,
-            _ => false,
-        }
+        matches!(
+            self,
+            MushroomValue::Float(_)
+                | MushroomValue::FloatArray(_)
+                | MushroomValue::Double(_)
+                | MushroomValue::DoubleArray(_)
+                | MushroomValue::Int(_)
+                | MushroomValue::IntArray(_)
+        )
     }
 
     pub fn is_string(&self) -> bool {
-        match self {
-            MushroomValue::String(_) => true,
-            MushroomValue::StringArray(_) => true,
-            _ => false,
-        }
+        matches!(self, MushroomValue::String(_) | MushroomValue::StringArray(_))
     }
 
     pub fn is_boolean(&self) -> bool {
-        match self {
-            MushroomValue::Boolean(_) => true,
-            MushroomValue::BooleanArray(_) => true,
-            _ => false,
-        }
+        matches!(self, MushroomValue::Boolean(_) | MushroomValue::BooleanArray(_))
     }
 
     pub fn is_array(&self) -> bool {
-        match self {
-            MushroomValue::ByteArray(_) => true,
-            MushroomValue::Protobuf(_) => true,
-            MushroomValue::FloatArray(_) => true,
-            MushroomValue::DoubleArray(_) => true,
-            MushroomValue::IntArray(_) => true,
-            MushroomValue::StringArray(_) => true,
-            MushroomValue::BooleanArray(_) => true,
-            _ => false,
-        }
+        matches!(
+            self,
+            MushroomValue::ByteArray(_)
+                | MushroomValue::Protobuf(_)
+                | MushroomValue::FloatArray(_)
+                | MushroomValue::DoubleArray(_)
+                | MushroomValue::IntArray(_)
+                | MushroomValue::StringArray(_)
+                | MushroomValue::BooleanArray(_)
+        )
     }
 
     pub fn is_single(&self) -> bool {
-        match self {
-            MushroomValue::Float(_) => true,
-            MushroomValue::Double(_) => true,
-            MushroomValue::Int(_) => true,
-            MushroomValue::String(_) => true,
-            MushroomValue::Boolean(_) => true,
-            _ => false,
-        }
+        matches!(
+            self,
+            MushroomValue::Float(_)
+                | MushroomValue::Double(_)
+                | MushroomValue::Int(_)
+                | MushroomValue::String(_)
+                | MushroomValue::Boolean(_)
+        )
     }
 
     pub fn get_index(&self, index: usize) -> Option<MushroomValue> {
@@ -234,6 +426,7 @@ impl MushroomValue {
             MushroomValue::IntArray(v) => Ok(T::from(MushroomValue::IntArray(v.clone()))),
             MushroomValue::StringArray(v) => Ok(T::from(MushroomValue::StringArray(v.clone()))),
             MushroomValue::BooleanArray(v) => Ok(T::from(MushroomValue::BooleanArray(v.clone()))),
+            MushroomValue::Struct(v) => Ok(T::from(MushroomValue::Struct(v.clone()))),
             // _ => Err(format!("Cannot convert {:?} to {}", self, std::any::type_name::<T>())),
         }
     }
@@ -305,6 +498,7 @@ impl From<MushroomValue> for Vec<u8> {
     fn from(m: MushroomValue) -> Self {
         match m {
             MushroomValue::ByteArray(v) => v,
+            MushroomValue::Struct(v) => v.raw,
             _ => panic!("Cannot convert {:?} to Vec<u8>", m),
         }
     }
@@ -325,7 +519,7 @@ impl From<MushroomValue> for Vec<f64> {
     fn from(m: MushroomValue) -> Self {
         match m {
             MushroomValue::DoubleArray(v) => v,
-            MushroomValue::FloatArray(v) => v.iter().map(|v| *v).collect(),
+            MushroomValue::FloatArray(v) => v,
             MushroomValue::IntArray(v) => v.iter().map(|v| *v as f64).collect(),
             _ => panic!("Cannot convert {:?} to Vec<f64>", m),
         }
@@ -371,11 +565,12 @@ impl From<MushroomValue> for rmpv::Value {
             MushroomValue::Boolean(v) => rmpv::Value::Boolean(v),
             MushroomValue::ByteArray(v) => rmpv::Value::Binary(v),
             MushroomValue::Protobuf(v) => rmpv::Value::Binary(v),
+            MushroomValue::Struct(v) => rmpv::Value::Binary(v.raw),
             MushroomValue::FloatArray(v) => {
                 rmpv::Value::Array(v.into_iter().map(|v| rmpv::Value::F32(v as f32)).collect())
             }
             MushroomValue::DoubleArray(v) => {
-                rmpv::Value::Array(v.into_iter().map(|v| rmpv::Value::F64(v)).collect())
+                rmpv::Value::Array(v.into_iter().map(rmpv::Value::F64).collect())
             }
             MushroomValue::IntArray(v) => rmpv::Value::Array(
                 v.into_iter()
@@ -388,56 +583,86 @@ impl From<MushroomValue> for rmpv::Value {
                     .collect(),
             ),
             MushroomValue::BooleanArray(v) => {
-                rmpv::Value::Array(v.into_iter().map(|v| rmpv::Value::Boolean(v)).collect())
+                rmpv::Value::Array(v.into_iter().map(rmpv::Value::Boolean).collect())
             }
         }
     }
 }
 
-impl From<rmpv::Value> for MushroomValue {
-    fn from(v: rmpv::Value) -> Self {
-        match v {
-            rmpv::Value::F32(v) => MushroomValue::Float(v as f64),
-            rmpv::Value::F64(v) => MushroomValue::Double(v),
-            rmpv::Value::Integer(v) => MushroomValue::Int(v.as_i64().unwrap_or_default()),
-            rmpv::Value::String(v) => MushroomValue::String(v.to_string().replace("\"", "")),
-            rmpv::Value::Boolean(v) => MushroomValue::Boolean(v),
-            rmpv::Value::Binary(v) => MushroomValue::ByteArray(v),
-            rmpv::Value::Array(v) => {
-                if v.len() == 0 {
-                    return MushroomValue::FloatArray(Vec::new());
-                }
-                match v[0] {
-                    rmpv::Value::F32(_) => MushroomValue::FloatArray(
-                        v.into_iter()
-                            .map(|v| v.as_f64().unwrap_or_default())
-                            .collect(),
-                    ),
-                    rmpv::Value::F64(_) => MushroomValue::DoubleArray(
-                        v.into_iter()
-                            .map(|v| v.as_f64().unwrap_or_default())
-                            .collect(),
-                    ),
-                    rmpv::Value::Integer(_) => MushroomValue::IntArray(
-                        v.into_iter()
-                            .map(|v| v.as_i64().unwrap_or_default())
-                            .collect(),
-                    ),
-                    rmpv::Value::String(_) => MushroomValue::StringArray(
-                        v.into_iter()
-                            .map(|v| v.as_str().unwrap_or("").to_owned())
-                            .collect(),
-                    ),
-                    rmpv::Value::Boolean(_) => MushroomValue::BooleanArray(
-                        v.into_iter()
-                            .map(|v| v.as_bool().unwrap_or_default())
-                            .collect(),
-                    ),
-                    _ => panic!("Cannot convert {:?} to MushroomTypes", v),
-                }
+/// Decodes a value of a topic with the NT4 type string it was announced with,
+/// the value has to match that type
+impl TryFrom<(&str, rmpv::Value)> for MushroomValue {
+    type Error = String;
+
+    fn try_from((type_name, v): (&str, rmpv::Value)) -> Result<Self, Self::Error> {
+        fn elements(v: rmpv::Value) -> Result<Vec<rmpv::Value>, String> {
+            match v {
+                rmpv::Value::Array(v) => Ok(v),
+                other => Err(format!("expected an array, got {}", other)),
+            }
+        }
+        fn array<T>(
+            v: rmpv::Value,
+            element: impl Fn(rmpv::Value) -> Result<T, String>,
+        ) -> Result<Vec<T>, String> {
+            elements(v)?.into_iter().map(element).collect()
+        }
+        fn float(v: rmpv::Value) -> Result<f64, String> {
+            match v {
+                rmpv::Value::F32(v) => Ok(v as f64),
+                rmpv::Value::F64(v) => Ok(v),
+                rmpv::Value::Integer(v) => v
+                    .as_f64()
+                    .ok_or_else(|| format!("{} is not a number", v)),
+                other => Err(format!("expected a number, got {}", other)),
+            }
+        }
+        fn int(v: rmpv::Value) -> Result<i64, String> {
+            match v {
+                rmpv::Value::Integer(v) => v
+                    .as_i64()
+                    .ok_or_else(|| format!("{} does not fit in an int", v)),
+                other => Err(format!("expected an integer, got {}", other)),
             }
-            _ => panic!("Cannot convert {:?} to MushroomTypes", v),
         }
+        fn string(v: rmpv::Value) -> Result<String, String> {
+            match v {
+                rmpv::Value::String(v) => v
+                    .into_str()
+                    .ok_or_else(|| String::from("string is not valid utf-8")),
+                other => Err(format!("expected a string, got {}", other)),
+            }
+        }
+        fn boolean(v: rmpv::Value) -> Result<bool, String> {
+            match v {
+                rmpv::Value::Boolean(v) => Ok(v),
+                other => Err(format!("expected a boolean, got {}", other)),
+            }
+        }
+
+        Ok(match type_name {
+            "boolean" => MushroomValue::Boolean(boolean(v)?),
+            "double" => MushroomValue::Double(float(v)?),
+            "float" => MushroomValue::Float(float(v)?),
+            "int" => MushroomValue::Int(int(v)?),
+            "string" | "json" => MushroomValue::String(string(v)?),
+            "boolean[]" => MushroomValue::BooleanArray(array(v, boolean)?),
+            "double[]" => MushroomValue::DoubleArray(array(v, float)?),
+            "float[]" => MushroomValue::FloatArray(array(v, float)?),
+            "int[]" => MushroomValue::IntArray(array(v, int)?),
+            "string[]" => MushroomValue::StringArray(array(v, string)?),
+            //msgpack topics can hold any value, it is kept in its encoded form
+            "msgpack" => {
+                let mut buf = Vec::new();
+                rmpv::encode::write_value(&mut buf, &v).map_err(|err| err.to_string())?;
+                MushroomValue::ByteArray(buf)
+            }
+            _ => match v {
+                //raw, rpc, protobuf, struct and schema topics all carry bytes
+                rmpv::Value::Binary(v) => MushroomValue::ByteArray(v),
+                other => return Err(format!("expected bytes for {}, got {}", type_name, other)),
+            },
+        })
     }
 }
 
@@ -456,6 +681,7 @@ impl From<MushroomValue> for network_tables::v4::message_type::Type {
             MushroomValue::StringArray(_) => network_tables::v4::message_type::Type::StringArray,
             MushroomValue::Protobuf(_) => network_tables::v4::message_type::Type::ProtoBuf,
             MushroomValue::ByteArray(_) => network_tables::v4::message_type::Type::Raw,
+            MushroomValue::Struct(_) => network_tables::v4::message_type::Type::Raw,
         }
     }
 }
@@ -496,7 +722,8 @@ impl From<MushroomValue> for DataLogValue {
             MushroomValue::IntArray(v) => DataLogValue::IntegerArray(v),
             MushroomValue::StringArray(v) => DataLogValue::StringArray(v),
             MushroomValue::ByteArray(v) => DataLogValue::Raw(v),
-            _ => panic!("Cannot convert {:?} to DataLogValue", m),
+            MushroomValue::Protobuf(v) => DataLogValue::Raw(v),
+            MushroomValue::Struct(v) => DataLogValue::Raw(v.raw),
         }
     }
 }
@@ -528,6 +755,47 @@ impl From<&str> for MushroomPath {
     }
 }
 
+impl MushroomPath {
+    /// The parts of the path, absolute paths start with an empty one
+    pub fn segments(&self) -> &[String] {
+        &self.path
+    }
+
+    /// `prefix` has to match whole segments, `/a/b` is under `/a` but not under `/a/b2`,
+    /// a trailing `/` makes no difference
+    pub fn starts_with(&self, prefix: &MushroomPath) -> bool {
+        let prefix = prefix.trimmed();
+        self.path.len() >= prefix.len() && self.path[..prefix.len()] == *prefix
+    }
+
+    /// `*` in `pattern` matches exactly one segment and `**` any number of them,
+    /// e.g. `/SmartDashboard/*/velocity` or `/Shuffleboard/**`
+    pub fn matches(&self, pattern: &MushroomPath) -> bool {
+        fn matches(path: &[String], pattern: &[String]) -> bool {
+            match pattern.split_first() {
+                None => path.is_empty(),
+                Some((first, rest)) if first == "**" => {
+                    (0..=path.len()).any(|skip| matches(&path[skip..], rest))
+                }
+                Some((first, rest)) => match path.split_first() {
+                    Some((segment, path)) => {
+                        (first == "*" || first == segment) && matches(path, rest)
+                    }
+                    None => false,
+                },
+            }
+        }
+        matches(&self.path, pattern.trimmed())
+    }
+
+    fn trimmed(&self) -> &[String] {
+        match self.path.split_last() {
+            Some((last, rest)) if last.is_empty() => rest,
+            _ => &self.path,
+        }
+    }
+}
+
 impl Serialize for MushroomPath {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -561,11 +829,12 @@ impl Display for MushroomPath {
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Serialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct MushroomEntry {
     value: MushroomValue,
     path: MushroomPath,
-    timestamp: Option<f64>,
+    /// `None` for values that were never sent
+    timestamp: Option<ServerTime>,
 }
 
 impl Display for MushroomEntry {
@@ -575,7 +844,7 @@ impl Display for MushroomEntry {
 }
 
 impl MushroomEntry {
-    pub fn new(value: MushroomValue, path: MushroomPath, timestamp: Option<f64>) -> Self {
+    pub fn new(value: MushroomValue, path: MushroomPath, timestamp: Option<ServerTime>) -> Self {
         Self {
             value,
             path,
@@ -591,22 +860,41 @@ impl MushroomEntry {
         self.value.clone()
     }
 
-    pub fn get_timestamp(&self) -> Option<f64> {
-        self.timestamp.clone()
+    pub fn get_timestamp(&self) -> Option<ServerTime> {
+        self.timestamp
     }
 }
 
 #[derive(Clone, Debug)]
 pub struct MushroomTable {
-    timestamp: MushroomTimeStamp,
+    timestamp: ServerTime,
     //could use a set but this is easier
     entries: Vec<MushroomEntry>,
     entry_paths: HashMap<MushroomPath, usize>,
+    /// Goes up by one with every change to the entries
+    version: u64,
+    /// The version each entry last changed at, same order as `entries`
+    entry_versions: Vec<u64>,
+    /// Removed paths and the version they were removed at
+    removed: HashMap<MushroomPath, u64>,
+    /// Removals up to this version were dropped from `removed` to keep it bounded
+    forgotten: u64,
+    /// Tells this table apart from the ones it replaced, clones share it
+    epoch: u64,
+}
+
+/// How many removed paths a table keeps for its deltas
+const KEPT_REMOVALS: usize = 1024;
+
+static NEXT_TABLE_EPOCH: AtomicU64 = AtomicU64::new(1);
+
+fn next_table_epoch() -> u64 {
+    NEXT_TABLE_EPOCH.fetch_add(1, Ordering::Relaxed)
 }
 
 impl Display for MushroomTable {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        writeln!(f, "Table at {}", self.timestamp)?;
+        writeln!(f, "Table at {}", self.timestamp.0)?;
         for entry in &self.entries {
             writeln!(f, "{}", entry)?;
         }
@@ -615,33 +903,51 @@ impl Display for MushroomTable {
 }
 
 impl MushroomTable {
-    pub fn new(timestamp: MushroomTimeStamp) -> Self {
+    pub fn new(timestamp: ServerTime) -> Self {
         Self {
             timestamp,
             entries: Vec::new(),
             entry_paths: HashMap::new(),
+            version: 0,
+            entry_versions: Vec::new(),
+            removed: HashMap::new(),
+            forgotten: 0,
+            epoch: next_table_epoch(),
         }
     }
 
-    pub fn new_from_entries(timestamp: MushroomTimeStamp, entries: Vec<MushroomEntry>) -> Self {
+    pub fn new_from_entries(timestamp: ServerTime, entries: Vec<MushroomEntry>) -> Self {
         let mut entry_paths = HashMap::new();
         for (i, entry) in entries.iter().enumerate() {
-            entry_paths.insert(entry.get_path().into(), i);
+            entry_paths.insert(entry.get_path(), i);
         }
         Self {
             timestamp,
+            version: entries.len() as u64,
+            entry_versions: (1..=entries.len() as u64).collect(),
             entries,
             entry_paths,
+            removed: HashMap::new(),
+            forgotten: 0,
+            epoch: next_table_epoch(),
         }
     }
 
     pub fn add_entry(&mut self, entry: MushroomEntry) {
         if self.has_entry(&entry.get_path()) {
-            let index = self.entry_paths.get(&entry.get_path()).unwrap();
-            self.entries[*index] = entry;
+            let index = *self.entry_paths.get(&entry.get_path()).unwrap();
+            if self.entries[index] == entry {
+                return;
+            }
+            self.version += 1;
+            self.entries[index] = entry;
+            self.entry_versions[index] = self.version;
         } else {
             let path = entry.get_path();
+            self.version += 1;
+            self.removed.remove(&path);
             self.entries.push(entry);
+            self.entry_versions.push(self.version);
             self.entry_paths.insert(path, self.entries.len() - 1);
         }
     }
@@ -655,16 +961,84 @@ impl MushroomTable {
         }
     }
 
+    pub fn remove_entry(&mut self, path: &MushroomPath) -> Option<MushroomEntry> {
+        let index = self.entry_paths.remove(path)?;
+        let entry = self.entries.swap_remove(index);
+        self.entry_versions.swap_remove(index);
+        if let Some(moved) = self.entries.get(index) {
+            self.entry_paths.insert(moved.get_path(), index);
+        }
+        self.version += 1;
+        self.removed.insert(path.clone(), self.version);
+        if self.removed.len() > KEPT_REMOVALS {
+            self.forget_removals();
+        }
+        Some(entry)
+    }
+
+    /// Drops the older half of the removals, deltas reaching back before them are resets
+    fn forget_removals(&mut self) {
+        let mut versions: Vec<u64> = self.removed.values().copied().collect();
+        versions.sort_unstable();
+        let forgotten = versions[versions.len() / 2];
+        self.removed.retain(|_, version| *version > forgotten);
+        self.forgotten = forgotten;
+    }
+
     pub fn get_entries(&self) -> &Vec<MushroomEntry> {
         &self.entries
     }
 
-    pub fn get_timestamp(&self) -> MushroomTimeStamp {
+    pub fn get_timestamp(&self) -> ServerTime {
         self.timestamp
     }
 
+    pub fn get_version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn get_epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// What changed after version `since` of the table with `epoch`,
+    /// everything is sent again if that was another table
+    /// or its removals since then were forgotten
+    pub fn delta_since(&self, epoch: u64, since: u64) -> MushroomTableDelta {
+        //a caller starting at 0 has nothing to reset
+        if since > 0 && (epoch != self.epoch || since > self.version || since < self.forgotten) {
+            return MushroomTableDelta {
+                epoch: self.epoch,
+                version: self.version,
+                timestamp: self.timestamp,
+                reset: true,
+                entries: self.entries.clone(),
+                removed: Vec::new(),
+            };
+        }
+        MushroomTableDelta {
+            epoch: self.epoch,
+            version: self.version,
+            timestamp: self.timestamp,
+            reset: false,
+            entries: self
+                .entries
+                .iter()
+                .zip(&self.entry_versions)
+                .filter(|(_, version)| **version > since)
+                .map(|(entry, _)| entry.clone())
+                .collect(),
+            removed: self
+                .removed
+                .iter()
+                .filter(|(_, version)| **version > since)
+                .map(|(path, _)| path.clone())
+                .collect(),
+        }
+    }
+
     pub fn has_entry(&self, path: &MushroomPath) -> bool {
-        self.entry_paths.contains_key(&path)
+        self.entry_paths.contains_key(path)
     }
 
     pub fn is_empty(&self) -> bool {
@@ -681,6 +1055,10 @@ impl MushroomTable {
         }
     }
 
+    pub fn set_timestamp(&mut self, timestamp: ServerTime) {
+        self.timestamp = timestamp;
+    }
+
     pub fn update_timestamp(&mut self, other: &MushroomTable) {
         self.timestamp = other.get_timestamp();
     }
@@ -689,6 +1067,96 @@ impl MushroomTable {
         self.update_entries(other);
         self.update_timestamp(other);
     }
+
+    /// The entries at or under `prefix`
+    pub fn entries_under(&self, prefix: &MushroomPath) -> MushroomTable {
+        self.filtered(|path| path.starts_with(prefix))
+    }
+
+    /// The entries whose path matches the glob `pattern`, see [`MushroomPath::matches`]
+    pub fn entries_matching(&self, pattern: &MushroomPath) -> MushroomTable {
+        self.filtered(|path| path.matches(pattern))
+    }
+
+    /// The names of the segments directly under `path` that have entries at or below them, sorted
+    pub fn children(&self, path: &MushroomPath) -> Vec<String> {
+        let depth = path.trimmed().len();
+        let mut children: Vec<String> = self
+            .entries
+            .iter()
+            .map(|entry| &entry.path)
+            .filter(|entry_path| {
+                entry_path.segments().len() > depth && entry_path.starts_with(path)
+            })
+            .map(|entry_path| entry_path.segments()[depth].clone())
+            .collect();
+        children.sort();
+        children.dedup();
+        children
+    }
+
+    /// The entries at or under `prefix` nested by path segment
+    pub fn tree(&self, prefix: &MushroomPath) -> MushroomTree {
+        let depth = prefix.trimmed().len();
+        let mut tree = MushroomTree::default();
+        for entry in &self.entries {
+            if entry.path.starts_with(prefix) {
+                tree.insert(&entry.path.segments()[depth..], entry);
+            }
+        }
+        tree
+    }
+
+    fn filtered(&self, keep: impl Fn(&MushroomPath) -> bool) -> MushroomTable {
+        let entries = self
+            .entries
+            .iter()
+            .filter(|entry| keep(&entry.path))
+            .cloned()
+            .collect();
+        MushroomTable::new_from_entries(self.timestamp, entries)
+    }
+}
+
+/// The changes of a table after a version, see [`MushroomTable::delta_since`]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MushroomTableDelta {
+    /// Pass this as `epoch` to get the next delta
+    pub epoch: u64,
+    /// Pass this as `since` to get the next delta
+    pub version: u64,
+    pub timestamp: ServerTime,
+    /// Every entry is in `entries`, anything else held for the table is gone
+    pub reset: bool,
+    pub entries: Vec<MushroomEntry>,
+    pub removed: Vec<MushroomPath>,
+}
+
+/// A node of [`MushroomTable::tree`], a path can have a value and children at the same time
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct MushroomTree {
+    pub entry: Option<MushroomEntry>,
+    pub children: BTreeMap<String, MushroomTree>,
+}
+
+impl MushroomTree {
+    fn insert(&mut self, segments: &[String], entry: &MushroomEntry) {
+        match segments.split_first() {
+            Some((first, rest)) => self
+                .children
+                .entry(first.clone())
+                .or_default()
+                .insert(rest, entry),
+            None => self.entry = Some(entry.clone()),
+        }
+    }
+
+    pub fn get(&self, segments: &[&str]) -> Option<&MushroomTree> {
+        match segments.split_first() {
+            Some((first, rest)) => self.children.get(*first)?.get(rest),
+            None => Some(self),
+        }
+    }
 }
 
 impl Serialize for MushroomTable {
@@ -704,4 +1172,25 @@ impl Serialize for MushroomTable {
     }
 }
 
+/// Reads the entry array tables are serialized as, the array has no table
+/// timestamp so it is the newest entry timestamp
+impl<'de> Deserialize<'de> for MushroomTable {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let entries = Vec::<MushroomEntry>::deserialize(deserializer)?;
+        let timestamp = entries
+            .iter()
+            .filter_map(MushroomEntry::get_timestamp)
+            .max()
+            .unwrap_or_default();
+        let mut table = MushroomTable::new(timestamp);
+        for entry in entries {
+            table.add_entry(entry);
+        }
+        Ok(table)
+    }
+}
+
 // pub type MushroomTable = HashSet<MushroomEntry>;
\ No newline at end of file
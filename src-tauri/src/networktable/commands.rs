@@ -1,34 +1,65 @@
-use std::net::Ipv4Addr;
+use std::collections::HashMap;
+use std::time::Duration;
 
 use network_tables::v4::SubscriptionOptions;
+use tauri::AppHandle;
 
-use crate::{NETWORK_CLIENT_MAP, mushroom_types::{MushroomEntry, MushroomPath, MushroomTable, MushroomValue}, networktable::handler::{SubscriptionPackage, start_nt4_client}};
+use crate::{NETWORK_CLIENT_MAP, NETWORK_SERVER, mushroom_types::{MushroomEntry, MushroomPath, MushroomTable, MushroomTableDelta, MushroomTree, MushroomValue}, networktable::handler::{SubscriptionPackage, start_nt4_client}};
+use crate::error::EnokiError;
+use crate::time::ServerTime;
 
-use super::handler::NetworkTableClientId;
+use super::connection::{ConnectionPolicy, ConnectionStatus};
+use super::handler::{NetworkTableClientConfig, NetworkTableClientId, SubscriptionInfo};
+use super::history::HistoryConfig;
+use super::metrics::LinkMetrics;
+use super::server::{start_nt4_server, DEFAULT_NT4_PORT};
+use super::topics::{TopicInfo, TopicProperties};
 
 
 #[tauri::command]
 pub fn start_network_table_client(
-    address: [u8; 4],
+    app_handle: AppHandle,
+    host: String,
     port: u16,
     identity: String,
-) -> NetworkTableClientId {
-    let ip = Ipv4Addr::from(address);
-    let id = NetworkTableClientId::new(ip, port, identity.clone());
+    policy: Option<ConnectionPolicy>,
+) -> Result<NetworkTableClientId, EnokiError> {
+    let id = NetworkTableClientId::new(host, port, identity);
+    restart_network_table_client(app_handle, id, policy.unwrap_or_default())
+}
+
+#[tauri::command]
+pub fn start_network_table_client_for_team(
+    app_handle: AppHandle,
+    team: u16,
+    port: u16,
+    identity: String,
+    policy: Option<ConnectionPolicy>,
+) -> Result<NetworkTableClientId, EnokiError> {
+    let id = NetworkTableClientId::new_for_team(team, port, identity);
+    restart_network_table_client(app_handle, id, policy.unwrap_or_default())
+}
 
-    if let Some(client) = NETWORK_CLIENT_MAP.with(|map| map.borrow_mut().remove(&id)) {
+/// Stops any client already running under `id` before starting a new one
+fn restart_network_table_client(
+    app_handle: AppHandle,
+    id: NetworkTableClientId,
+    policy: ConnectionPolicy,
+) -> Result<NetworkTableClientId, EnokiError> {
+    policy.validate()?;
+    if let Some(mut client) = NETWORK_CLIENT_MAP.with(|map| map.borrow_mut().remove(&id)) {
         tracing::info!("Stopping network table client for {}", id);
         client.stop();
     }
 
     tracing::info!("Starting network table client for {}", id);
-    let client = start_nt4_client(ip, port, identity).unwrap();
+    let client = start_nt4_client(app_handle, id.clone(), policy)?;
 
     NETWORK_CLIENT_MAP.with(|map| {
         map.borrow_mut().insert(id.clone(), client);
     });
 
-    return id;
+    Ok(id)
 }
 
 #[tauri::command]
@@ -36,10 +67,122 @@ pub fn does_network_table_client_exist(client_id: NetworkTableClientId) -> bool
     NETWORK_CLIENT_MAP.with(|map| map.borrow().contains_key(&client_id))
 }
 
+#[tauri::command]
+pub fn get_network_table_client_state(client_id: NetworkTableClientId) -> Option<ConnectionStatus> {
+    NETWORK_CLIENT_MAP.with(|map| {
+        if let Some(client) = map.borrow().get(&client_id) {
+            Some(client.connection_status())
+        } else {
+            tracing::warn!("No network table client found for {}", client_id);
+            None
+        }
+    })
+}
+
+#[tauri::command]
+pub fn get_network_table_client_config(
+    client_id: NetworkTableClientId,
+) -> Option<NetworkTableClientConfig> {
+    NETWORK_CLIENT_MAP.with(|map| {
+        if let Some(client) = map.borrow().get(&client_id) {
+            Some(client.config().clone())
+        } else {
+            tracing::warn!("No network table client found for {}", client_id);
+            None
+        }
+    })
+}
+
+#[tauri::command]
+pub fn get_network_table_client_metrics(client_id: NetworkTableClientId) -> Option<LinkMetrics> {
+    NETWORK_CLIENT_MAP.with(|map| {
+        if let Some(client) = map.borrow().get(&client_id) {
+            Some(client.link_metrics())
+        } else {
+            tracing::warn!("No network table client found for {}", client_id);
+            None
+        }
+    })
+}
+
+#[tauri::command]
+pub fn list_topics(client_id: NetworkTableClientId, prefix: Option<String>) -> Vec<TopicInfo> {
+    NETWORK_CLIENT_MAP.with(|map| {
+        if let Some(client) = map.borrow().get(&client_id) {
+            client.list_topics(prefix.as_deref())
+        } else {
+            tracing::warn!("No network table client found for {}", client_id);
+            Vec::new()
+        }
+    })
+}
+
+#[tauri::command]
+pub fn get_topic_history(
+    client_id: NetworkTableClientId,
+    path: MushroomPath,
+    since: Option<ServerTime>,
+) -> Vec<MushroomEntry> {
+    NETWORK_CLIENT_MAP.with(|map| {
+        if let Some(client) = map.borrow().get(&client_id) {
+            client.history_since(&path, since)
+        } else {
+            tracing::warn!("No network table client found for {}", client_id);
+            Vec::new()
+        }
+    })
+}
+
+#[tauri::command]
+pub fn set_history_config(
+    client_id: NetworkTableClientId,
+    config: HistoryConfig,
+) -> Result<(), EnokiError> {
+    NETWORK_CLIENT_MAP.with(|map| {
+        if let Some(client) = map.borrow().get(&client_id) {
+            client.set_history_config(config);
+            Ok(())
+        } else {
+            Err(EnokiError::NoClient(client_id.repr()))
+        }
+    })
+}
+
+#[tauri::command]
+pub fn set_update_coalescing(
+    client_id: NetworkTableClientId,
+    millis: u64,
+) -> Result<(), EnokiError> {
+    NETWORK_CLIENT_MAP.with(|map| {
+        if let Some(client) = map.borrow().get(&client_id) {
+            client.set_coalescing(Duration::from_millis(millis));
+            Ok(())
+        } else {
+            Err(EnokiError::NoClient(client_id.repr()))
+        }
+    })
+}
+
+#[tauri::command]
+pub fn set_network_table_recording(
+    client_id: NetworkTableClientId,
+    recording: bool,
+    prefix: Option<String>,
+) -> Result<(), EnokiError> {
+    NETWORK_CLIENT_MAP.with(|map| {
+        if let Some(client) = map.borrow_mut().get_mut(&client_id) {
+            client.set_recording(recording, prefix);
+            Ok(())
+        } else {
+            Err(EnokiError::NoClient(client_id.repr()))
+        }
+    })
+}
+
 #[tauri::command]
 pub fn stop_network_table_client(client_id: NetworkTableClientId) {
     NETWORK_CLIENT_MAP.with(|map| {
-        if let Some(client) = map.borrow_mut().remove(&client_id) {
+        if let Some(mut client) = map.borrow_mut().remove(&client_id) {
             tracing::info!("Stopping network table cleint for {}", client_id);
             client.stop();
         } else {
@@ -76,13 +219,11 @@ pub fn subscribe_to_topic(
 }
 
 #[tauri::command]
-pub fn set_boolean_topic(client_id: NetworkTableClientId, topic: String, value: bool) {
+pub fn unsubscribe_from_topic(client_id: NetworkTableClientId, topic: String) {
     NETWORK_CLIENT_MAP.with(|map| {
         if let Some(client) = map.borrow_mut().get_mut(&client_id) {
-            let entry =
-                MushroomEntry::new(MushroomValue::Boolean(value), topic.clone().into(), None);
-            client.publish(MushroomTable::new_from_entries(0, vec![entry]));
-            tracing::info!("Set boolean topic {} to {}", topic, value);
+            client.unsubscribe(vec![topic.clone()]);
+            tracing::info!("Unsubscribed from topic {}", topic);
         } else {
             tracing::warn!("No network table client found for {}", client_id);
         }
@@ -90,172 +231,205 @@ pub fn set_boolean_topic(client_id: NetworkTableClientId, topic: String, value:
 }
 
 #[tauri::command]
-pub fn set_float_topic(client_id: NetworkTableClientId, topic: String, value: f64) {
+pub fn list_subscriptions(client_id: NetworkTableClientId) -> Vec<SubscriptionInfo> {
     NETWORK_CLIENT_MAP.with(|map| {
-        if let Some(client) = map.borrow_mut().get_mut(&client_id) {
-            let entry = MushroomEntry::new(MushroomValue::Float(value), topic.clone().into(), None);
-            client.publish(MushroomTable::new_from_entries(0, vec![entry]));
-            tracing::info!("Set float topic {} to {}", topic, value);
+        if let Some(client) = map.borrow().get(&client_id) {
+            client.list_subscriptions()
         } else {
             tracing::warn!("No network table client found for {}", client_id);
+            Vec::new()
         }
-    });
+    })
+}
+
+/// One value of a [`publish_to_topics`] batch,
+/// the value is given as the `type` and `value` a [`MushroomValue`] serializes with
+#[derive(Debug, serde::Deserialize)]
+pub struct PublishItem {
+    path: String,
+    #[serde(flatten)]
+    value: MushroomValue,
+    properties: Option<TopicProperties>,
+}
+
+/// The values of a batch as a table and the properties given per path,
+/// a path can only be in a batch once
+pub fn publish_batch(
+    items: Vec<PublishItem>,
+) -> Result<(MushroomTable, HashMap<String, TopicProperties>), EnokiError> {
+    let mut table = MushroomTable::new(ServerTime::default());
+    let mut properties = HashMap::new();
+    for item in items {
+        if item.path.is_empty() {
+            return Err(EnokiError::InvalidValue(String::from("empty topic path")));
+        }
+        let path = MushroomPath::from(item.path.as_str());
+        if table.has_entry(&path) {
+            return Err(EnokiError::InvalidValue(format!(
+                "{} is in the batch more than once",
+                item.path
+            )));
+        }
+        table.add_entry(MushroomEntry::new(item.value, path, None));
+        if let Some(item_properties) = item.properties {
+            properties.insert(item.path, item_properties);
+        }
+    }
+    Ok((table, properties))
 }
 
 #[tauri::command]
-pub fn set_double_topic(client_id: NetworkTableClientId, topic: String, value: f64) {
+pub fn publish_to_topics(
+    client_id: NetworkTableClientId,
+    items: Vec<PublishItem>,
+) -> Result<(), EnokiError> {
+    let (table, properties) = publish_batch(items)?;
     NETWORK_CLIENT_MAP.with(|map| {
         if let Some(client) = map.borrow_mut().get_mut(&client_id) {
-            let entry =
-                MushroomEntry::new(MushroomValue::Double(value), topic.clone().into(), None);
-            client.publish(MushroomTable::new_from_entries(0, vec![entry]));
-            tracing::info!("Set double topic {} to {}", topic, value);
+            tracing::info!("Publishing {} topics for {}", table.len(), client_id);
+            client.publish(table, properties)
         } else {
-            tracing::warn!("No network table client found for {}", client_id);
+            Err(EnokiError::NoClient(client_id.repr()))
         }
-    });
+    })
 }
 
 #[tauri::command]
-pub fn set_string_topic(client_id: NetworkTableClientId, topic: String, value: String) {
+pub fn unpublish_topics(
+    client_id: NetworkTableClientId,
+    paths: Vec<String>,
+) -> Result<(), EnokiError> {
     NETWORK_CLIENT_MAP.with(|map| {
         if let Some(client) = map.borrow_mut().get_mut(&client_id) {
-            let entry = MushroomEntry::new(
-                MushroomValue::String(value.clone()),
-                topic.clone().into(),
-                None,
-            );
-            client.publish(MushroomTable::new_from_entries(0, vec![entry]));
-            tracing::info!("Set string topic {} to {}", topic, value);
+            client.unpublish(paths)
         } else {
-            tracing::warn!("No network table client found for {}", client_id);
+            Err(EnokiError::NoClient(client_id.repr()))
         }
-    });
+    })
 }
 
 #[tauri::command]
-pub fn set_int_topic(client_id: NetworkTableClientId, topic: String, value: i64) {
+pub fn set_topic_properties(
+    client_id: NetworkTableClientId,
+    path: String,
+    properties: TopicProperties,
+) -> Result<(), EnokiError> {
     NETWORK_CLIENT_MAP.with(|map| {
         if let Some(client) = map.borrow_mut().get_mut(&client_id) {
-            let entry = MushroomEntry::new(MushroomValue::Int(value), topic.clone().into(), None);
-            client.publish(MushroomTable::new_from_entries(0, vec![entry]));
-            tracing::info!("Set int topic {} to {} for {}", topic, value, client_id);
+            client.set_properties(path, properties)
         } else {
-            tracing::warn!("No network table client found for {}", client_id);
+            Err(EnokiError::NoClient(client_id.repr()))
         }
-    });
+    })
 }
 
 #[tauri::command]
-pub fn set_boolean_array_topic(client_id: NetworkTableClientId, topic: String, value: Vec<bool>) {
+pub fn get_subbed_entries_values(client_id: NetworkTableClientId) -> MushroomTable {
     NETWORK_CLIENT_MAP.with(|map| {
         if let Some(client) = map.borrow_mut().get_mut(&client_id) {
-            let entry = MushroomEntry::new(
-                MushroomValue::BooleanArray(value.clone()),
-                topic.clone().into(),
-                None,
-            );
-            client.publish(MushroomTable::new_from_entries(0, vec![entry]));
-            tracing::info!("Set boolean array topic {} to {:?}", topic, value);
+            tracing::info!("Getting subbed entries values for {}", client_id);
+            client.poll()
         } else {
             tracing::warn!("No network table client found for {}", client_id);
+            MushroomTable::new(ServerTime::default())
         }
-    });
+    })
 }
 
+/// Pass the `epoch` and `version` of the last delta as `epoch` and `since`, 0 gets every entry
 #[tauri::command]
-pub fn set_float_array_topic(client_id: NetworkTableClientId, topic: String, value: Vec<f64>) {
+pub fn get_subbed_entries_delta(client_id: NetworkTableClientId, epoch: u64, since: u64) -> MushroomTableDelta {
     NETWORK_CLIENT_MAP.with(|map| {
         if let Some(client) = map.borrow_mut().get_mut(&client_id) {
-            let entry = MushroomEntry::new(
-                MushroomValue::FloatArray(value.clone()),
-                topic.clone().into(),
-                None,
-            );
-            client.publish(MushroomTable::new_from_entries(0, vec![entry]));
-            tracing::info!("Set float array topic {} to {:?}", topic, value);
+            tracing::info!("Getting subbed entries delta since {} for {}", since, client_id);
+            client.poll_delta(epoch, since)
         } else {
             tracing::warn!("No network table client found for {}", client_id);
+            MushroomTable::new(ServerTime::default()).delta_since(epoch, since)
         }
-    });
+    })
 }
 
 #[tauri::command]
-pub fn set_double_array_topic(client_id: NetworkTableClientId, topic: String, value: Vec<f64>) {
+pub fn get_subbed_entry_value(
+    client_id: NetworkTableClientId,
+    path: MushroomPath,
+) -> Option<MushroomEntry> {
     NETWORK_CLIENT_MAP.with(|map| {
         if let Some(client) = map.borrow_mut().get_mut(&client_id) {
-            let entry = MushroomEntry::new(
-                MushroomValue::DoubleArray(value.clone()),
-                topic.clone().into(),
-                None,
-            );
-            client.publish(MushroomTable::new_from_entries(0, vec![entry]));
-            tracing::info!("Set double array topic {} to {:?}", topic, value);
+            tracing::info!("Getting subbed entry value for {}", client_id);
+            client.poll().get_entry(&path)
         } else {
             tracing::warn!("No network table client found for {}", client_id);
+            None
         }
-    });
+    })
 }
 
+/// Every subscribed entry at or under `prefix`, e.g. everything under `/Shuffleboard/Drive`
 #[tauri::command]
-pub fn set_string_array_topic(client_id: NetworkTableClientId, topic: String, value: Vec<String>) {
+pub fn get_subbed_entries_under(
+    client_id: NetworkTableClientId,
+    prefix: MushroomPath,
+) -> MushroomTable {
     NETWORK_CLIENT_MAP.with(|map| {
         if let Some(client) = map.borrow_mut().get_mut(&client_id) {
-            let entry = MushroomEntry::new(
-                MushroomValue::StringArray(value.clone()),
-                topic.clone().into(),
-                None,
-            );
-            client.publish(MushroomTable::new_from_entries(0, vec![entry]));
-            tracing::info!("Set string array topic {} to {:?}", topic, value);
+            tracing::info!("Getting subbed entries under {} for {}", prefix, client_id);
+            client.poll().entries_under(&prefix)
         } else {
             tracing::warn!("No network table client found for {}", client_id);
+            MushroomTable::new(ServerTime::default())
         }
-    });
+    })
 }
 
+/// `pattern` can use `*` for one path segment and `**` for any number of them
 #[tauri::command]
-pub fn set_int_array_topic(client_id: NetworkTableClientId, topic: String, value: Vec<i64>) {
+pub fn get_subbed_entries_matching(
+    client_id: NetworkTableClientId,
+    pattern: MushroomPath,
+) -> MushroomTable {
     NETWORK_CLIENT_MAP.with(|map| {
         if let Some(client) = map.borrow_mut().get_mut(&client_id) {
-            let entry = MushroomEntry::new(
-                MushroomValue::IntArray(value.clone()),
-                topic.clone().into(),
-                None,
-            );
-            client.publish(MushroomTable::new_from_entries(0, vec![entry]));
-            tracing::info!("Set int array topic {} to {:?}", topic, value);
+            tracing::info!("Getting subbed entries matching {} for {}", pattern, client_id);
+            client.poll().entries_matching(&pattern)
         } else {
             tracing::warn!("No network table client found for {}", client_id);
+            MushroomTable::new(ServerTime::default())
         }
-    });
+    })
 }
 
 #[tauri::command]
-pub fn get_subbed_entries_values(client_id: NetworkTableClientId) -> MushroomTable {
+pub fn get_subbed_entry_children(
+    client_id: NetworkTableClientId,
+    path: MushroomPath,
+) -> Vec<String> {
     NETWORK_CLIENT_MAP.with(|map| {
         if let Some(client) = map.borrow_mut().get_mut(&client_id) {
-            tracing::info!("Getting subbed entries values for {}", client_id);
-            client.poll()
+            tracing::info!("Getting subbed entry children of {} for {}", path, client_id);
+            client.poll().children(&path)
         } else {
             tracing::warn!("No network table client found for {}", client_id);
-            MushroomTable::new(0)
+            Vec::new()
         }
     })
 }
 
+/// The subscribed entries nested by path segment, all of them when there is no prefix
 #[tauri::command]
-pub fn get_subbed_entry_value(
+pub fn get_subbed_entries_tree(
     client_id: NetworkTableClientId,
-    path: MushroomPath,
-) -> Option<MushroomEntry> {
+    prefix: Option<MushroomPath>,
+) -> MushroomTree {
+    let prefix = prefix.unwrap_or_else(|| MushroomPath::from("/"));
     NETWORK_CLIENT_MAP.with(|map| {
         if let Some(client) = map.borrow_mut().get_mut(&client_id) {
-            tracing::info!("Getting subbed entry value for {}", client_id);
-            client.poll().get_entry(&path)
+            tracing::info!("Getting subbed entries tree under {} for {}", prefix, client_id);
+            client.poll().tree(&prefix)
         } else {
             tracing::warn!("No network table client found for {}", client_id);
-            None
+            MushroomTree::default()
         }
     })
 }
@@ -265,10 +439,36 @@ pub fn get_client_timestamp(client_id: NetworkTableClientId) -> f64 {
     NETWORK_CLIENT_MAP.with(|map| {
         if let Some(client) = map.borrow_mut().get_mut(&client_id) {
             tracing::info!("Getting client timestamp for {}", client_id);
-            client.poll().get_timestamp() as f64 / 1000000_f64
+            client.poll().get_timestamp().as_secs()
         } else {
             tracing::warn!("No network table client found for {}", client_id);
             0_f64
         }
     })
-}
\ No newline at end of file
+}
+/// Replaces any server already running, returns the port it listens on
+#[tauri::command]
+pub fn start_network_table_server(port: Option<u16>) -> Result<u16, EnokiError> {
+    stop_network_table_server();
+    let port = port.unwrap_or(DEFAULT_NT4_PORT);
+    tracing::info!("Starting network table server on port {}", port);
+    let server = start_nt4_server(port)?;
+    //port 0 picks a free port, the caller needs to know which
+    let port = server.port();
+    NETWORK_SERVER.with(|server_slot| server_slot.borrow_mut().replace(server));
+    Ok(port)
+}
+
+#[tauri::command]
+pub fn stop_network_table_server() {
+    if let Some(server) = NETWORK_SERVER.with(|server| server.borrow_mut().take()) {
+        tracing::info!("Stopping network table server on port {}", server.port());
+        server.stop();
+    }
+}
+
+/// `None` while no server is running
+#[tauri::command]
+pub fn get_network_table_server_clients() -> Option<Vec<String>> {
+    NETWORK_SERVER.with(|server| server.borrow().as_ref().map(|server| server.client_names()))
+}
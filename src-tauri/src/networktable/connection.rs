@@ -0,0 +1,248 @@
+use std::fmt::Display;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tauri::{AppHandle, Manager};
+
+use crate::error::EnokiError;
+
+use super::handler::NetworkTableClientId;
+
+/// Emitted on every connection state transition,
+/// the payload is a [`ConnectionStateUpdate`]
+pub const NT_CONNECTION_EVENT: &str = "nt_connection";
+
+/// Emitted whenever the nt4 task hits an error it recovered from,
+/// the payload is a [`ConnectionErrorUpdate`]
+pub const NT_ERROR_EVENT: &str = "nt_error";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub enum ConnectionState {
+    Connecting,
+    Connected,
+    Reconnecting,
+    Failed,
+    Stopped,
+}
+
+impl Display for ConnectionState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// How a client connects and what it does when connecting fails or the connection is lost,
+/// missing fields keep their defaults
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct ConnectionPolicy {
+    /// How long a single connection attempt may take
+    pub connect_timeout_ms: u64,
+    /// How long to wait after the first failed attempt
+    pub retry_interval_ms: u64,
+    /// Every further failed attempt multiplies the wait by this
+    pub retry_backoff: f64,
+    pub max_retry_interval_ms: u64,
+    /// Failed attempts in a row before the client gives up, `None` never gives up
+    pub max_attempts: Option<u32>,
+    /// Whether to connect again after an established connection is lost
+    pub reconnect: bool,
+}
+
+impl Default for ConnectionPolicy {
+    fn default() -> Self {
+        Self {
+            connect_timeout_ms: 30000,
+            retry_interval_ms: 10000,
+            retry_backoff: 1.0,
+            max_retry_interval_ms: 60000,
+            max_attempts: None,
+            reconnect: true,
+        }
+    }
+}
+
+impl ConnectionPolicy {
+    pub fn validate(&self) -> Result<(), EnokiError> {
+        if !self.retry_backoff.is_finite() || self.retry_backoff < 1.0 {
+            return Err(EnokiError::InvalidValue(format!(
+                "retry backoff {} is less than 1",
+                self.retry_backoff
+            )));
+        }
+        if self.connect_timeout_ms == 0 {
+            return Err(EnokiError::InvalidValue(String::from(
+                "connect timeout of 0 never connects",
+            )));
+        }
+        if self.max_retry_interval_ms < self.retry_interval_ms {
+            return Err(EnokiError::InvalidValue(format!(
+                "max retry interval {}ms is less than the retry interval {}ms",
+                self.max_retry_interval_ms, self.retry_interval_ms
+            )));
+        }
+        if self.max_attempts == Some(0) {
+            return Err(EnokiError::InvalidValue(String::from(
+                "max attempts of 0 never connects",
+            )));
+        }
+        Ok(())
+    }
+
+    /// The wait after `failures` failed attempts in a row
+    pub fn retry_delay(&self, failures: u32) -> Duration {
+        let exponent = i32::try_from(failures.saturating_sub(1)).unwrap_or(i32::MAX);
+        let delay = self.retry_interval_ms as f64 * self.retry_backoff.powi(exponent);
+        Duration::from_millis(delay.min(self.max_retry_interval_ms as f64) as u64)
+    }
+
+    /// Whether another attempt is allowed after `failures` failed attempts in a row
+    pub fn may_retry(&self, failures: u32) -> bool {
+        self.max_attempts.map_or(true, |max| failures < max)
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ConnectionStatus {
+    state: ConnectionState,
+    previous: Option<ConnectionState>,
+    /// Unix milliseconds of the last transition
+    since: i64,
+    /// The address the client is actually connecting to
+    address: Option<String>,
+    last_error: Option<String>,
+}
+
+impl Default for ConnectionStatus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ConnectionStatus {
+    pub fn new() -> Self {
+        Self {
+            state: ConnectionState::Connecting,
+            previous: None,
+            since: chrono::Utc::now().timestamp_millis(),
+            address: None,
+            last_error: None,
+        }
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        self.state
+    }
+
+    pub fn previous(&self) -> Option<ConnectionState> {
+        self.previous
+    }
+
+    /// Returns false if the status already was in `state`
+    pub fn transition(&mut self, state: ConnectionState) -> bool {
+        if self.state == state {
+            return false;
+        }
+        self.previous = Some(self.state);
+        self.state = state;
+        self.since = chrono::Utc::now().timestamp_millis();
+        true
+    }
+
+    pub fn last_error(&self) -> Option<&str> {
+        self.last_error.as_deref()
+    }
+
+    /// Keeps the error as the last error, returns how it is reported
+    pub fn record_error(&mut self, err: &EnokiError) -> String {
+        let error = err.to_string();
+        self.last_error = Some(error.clone());
+        error
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ConnectionStateUpdate {
+    client_id: NetworkTableClientId,
+    status: ConnectionStatus,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ConnectionErrorUpdate {
+    client_id: NetworkTableClientId,
+    error: String,
+}
+
+/// Shared between a [`super::handler::NetworkTableClient`] and its nt4 task,
+/// every clone points at the same status
+#[derive(Debug, Clone)]
+pub struct ConnectionTracker {
+    id: NetworkTableClientId,
+    app_handle: AppHandle,
+    status: Arc<Mutex<ConnectionStatus>>,
+}
+
+impl ConnectionTracker {
+    pub fn new(id: NetworkTableClientId, app_handle: AppHandle) -> Self {
+        Self {
+            id,
+            app_handle,
+            status: Arc::new(Mutex::new(ConnectionStatus::new())),
+        }
+    }
+
+    pub fn status(&self) -> ConnectionStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    pub fn transition(&self, state: ConnectionState) {
+        let status = {
+            let mut status = self.status.lock().unwrap();
+            if !status.transition(state) {
+                return;
+            }
+            status.clone()
+        };
+        tracing::info!("Network table client {} is now {}", self.id, state);
+        self.app_handle
+            .emit_all(
+                NT_CONNECTION_EVENT,
+                ConnectionStateUpdate {
+                    client_id: self.id.clone(),
+                    status,
+                },
+            )
+            .unwrap_or_else(|err| {
+                tracing::error!(
+                    "Failed to emit connection state for {} because {}",
+                    self.id,
+                    err
+                );
+            });
+    }
+    pub fn address(&self) -> Option<String> {
+        self.status.lock().unwrap().address.clone()
+    }
+
+    /// Gets reported with the next transition
+    pub fn set_address(&self, address: String) {
+        self.status.lock().unwrap().address = Some(address);
+    }
+
+    /// Logs the error, keeps it as the last error of the status and emits it
+    pub fn report_error(&self, err: EnokiError) {
+        tracing::error!("Network table client {} error: {}", self.id, err);
+        let error = self.status.lock().unwrap().record_error(&err);
+        self.app_handle
+            .emit_all(
+                NT_ERROR_EVENT,
+                ConnectionErrorUpdate {
+                    client_id: self.id.clone(),
+                    error,
+                },
+            )
+            .unwrap_or_else(|err| {
+                tracing::error!("Failed to emit error for {} because {}", self.id, err);
+            });
+    }
+}
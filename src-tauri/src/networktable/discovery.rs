@@ -0,0 +1,57 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tokio::net::{lookup_host, TcpStream};
+
+use crate::error::EnokiError;
+
+/// How long a candidate gets to resolve and to accept a connection before the next one is tried,
+/// mDNS names of robots that aren't around can take seconds to fail
+const PROBE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// The radio network address of the roborio
+pub fn team_address(team: u16) -> String {
+    format!("10.{}.{}.2", team / 100, team % 100)
+}
+
+/// The standard robot addresses for a team in the order they should be tried,
+/// radio, mDNS, USB and finally localhost for simulation
+pub fn team_candidates(team: u16) -> Vec<String> {
+    vec![
+        team_address(team),
+        format!("roboRIO-{}-FRC.local", team),
+        String::from("172.22.11.2"),
+        String::from("127.0.0.1"),
+    ]
+}
+
+/// Returns the first candidate address that accepts a connection on `port`
+pub async fn discover_team_address(team: u16, port: u16) -> Result<SocketAddr, EnokiError> {
+    for candidate in team_candidates(team) {
+        let lookup = lookup_host((candidate.as_str(), port));
+        let addresses = match tokio::time::timeout(PROBE_TIMEOUT, lookup).await {
+            Ok(Ok(addresses)) => addresses,
+            Ok(Err(err)) => {
+                tracing::debug!("Could not resolve {} because {}", candidate, err);
+                continue;
+            }
+            Err(_) => {
+                tracing::debug!("Resolving {} timed out", candidate);
+                continue;
+            }
+        };
+        for address in addresses {
+            match tokio::time::timeout(PROBE_TIMEOUT, TcpStream::connect(address)).await {
+                Ok(Ok(_)) => {
+                    tracing::info!("Found team {} at {}", team, address);
+                    return Ok(address);
+                }
+                _ => tracing::debug!("Team {} is not at {}", team, address),
+            }
+        }
+    }
+    Err(EnokiError::NTConnect(format!(
+        "no robot address answered for team {}",
+        team
+    )))
+}
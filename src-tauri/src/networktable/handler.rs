@@ -1,21 +1,46 @@
-use network_tables::v4::client_config::default_should_reconnect;
 use network_tables::v4::subscription::SubscriptionOptions;
-use network_tables::v4::{Client, Config, PublishedTopic, Subscription, Type};
+use network_tables::v4::{
+    Client, Config, MessageData, PublishProperties, PublishedTopic, Subscription, Type,
+};
 use single_value_channel::{
     channel_starting_with as single_channel, Receiver as SingleReceiver, Updater as SingleUpdater,
 };
 use std::collections::HashMap;
 use std::fmt::Display;
 use std::hash::Hash;
-use std::net::{Ipv4Addr, SocketAddrV4};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tauri::{AppHandle, Manager};
+use tokio::net::lookup_host;
+use tokio::sync::mpsc::{channel, unbounded_channel, Receiver, Sender, UnboundedSender};
+use tokio::sync::{oneshot, watch, Notify};
 use tokio::task::JoinHandle as TokioJoinHandle;
+use tokio::time::Instant;
 
+use crate::datalog::replay::PlaybackControl;
 use crate::error::EnokiError;
-use crate::mushroom_types::{MushroomEntry, MushroomTable};
+use crate::mushroom_types::{
+    MushroomEntry, MushroomPath, MushroomTable, MushroomTableDelta, MushroomValue,
+};
+use crate::networktable::connection::{
+    ConnectionPolicy, ConnectionState, ConnectionStatus, ConnectionTracker,
+};
+use crate::networktable::discovery::{discover_team_address, team_address};
+use crate::networktable::history::{HistoryConfig, TopicHistory};
+use crate::networktable::metrics::{
+    encoded_len, log_metrics, start_metrics_entries, LinkMetrics, MetricsTracker, METRICS_INTERVAL,
+};
+use crate::networktable::recorder::{NtRecorder, RecordingSender};
+use crate::networktable::topics::{type_string, TopicCatalog, TopicInfo, TopicProperties};
+use crate::schema::{expand_entry, SchemaRegistry, SCHEMA_PREFIX};
+use crate::time::{ServerClock, ServerTime};
 use crate::{check_if_main_thread, NETWORK_CLIENT_MAP, THREAD_POOL};
 
+/// Emitted every loop iteration of an nt4 task that received new values,
+/// the payload is a [`NetworkTableUpdate`]
+pub const NT_UPDATE_EVENT: &str = "nt_update";
+
 pub fn get_connect_client_names() -> Vec<String> {
     let mut names = Vec::new();
     NETWORK_CLIENT_MAP.with(|map| {
@@ -28,16 +53,43 @@ pub fn get_connect_client_names() -> Vec<String> {
 
 #[derive(Debug, serde::Serialize, serde::Deserialize, Hash, PartialEq, Eq, Clone)]
 pub struct NetworkTableClientId {
-    ip: [u8; 4],
+    /// An ip address or a hostname, resolved every time the client connects
+    host: String,
     port: u16,
     identity: String,
+    team: Option<u16>,
+    /// The datalog a replay client plays, replays have no host or port
+    replay: Option<String>,
 }
 impl NetworkTableClientId {
-    pub fn new(ip: Ipv4Addr, port: u16, identity: String) -> Self {
+    pub fn new(host: String, port: u16, identity: String) -> Self {
         Self {
-            ip: ip.octets(),
+            host,
             port,
             identity,
+            team: None,
+            replay: None,
+        }
+    }
+
+    /// The host is only nominal, the real address is discovered when connecting
+    pub fn new_for_team(team: u16, port: u16, identity: String) -> Self {
+        Self {
+            host: team_address(team),
+            port,
+            identity,
+            team: Some(team),
+            replay: None,
+        }
+    }
+
+    pub fn new_for_replay(path: String) -> Self {
+        Self {
+            host: String::new(),
+            port: 0,
+            identity: String::from("replay"),
+            team: None,
+            replay: Some(path),
         }
     }
 
@@ -47,72 +99,256 @@ impl NetworkTableClientId {
 }
 impl Display for NetworkTableClientId {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{}:{}:{}",
-            Ipv4Addr::new(self.ip[0], self.ip[1], self.ip[2], self.ip[3]),
-            self.port,
-            self.identity
-        )
+        if let Some(path) = &self.replay {
+            return write!(f, "replay:{}", path);
+        }
+        if let Some(team) = self.team {
+            return write!(f, "team{}:{}:{}", team, self.port, self.identity);
+        }
+        //brackets keep the port distinguishable from the address
+        if let Ok(IpAddr::V6(ip)) = self.host.parse::<IpAddr>() {
+            return write!(f, "[{}]:{}:{}", ip, self.port, self.identity);
+        }
+        write!(f, "{}:{}:{}", self.host, self.port, self.identity)
     }
 }
 
+/// Only carries the entries that changed since the last update for `client_id`
+/// and the paths no subscription covers anymore
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct NetworkTableUpdate {
+    client_id: NetworkTableClientId,
+    entries: MushroomTable,
+    removed: Vec<MushroomPath>,
+}
+
+impl NetworkTableUpdate {
+    pub fn new(
+        client_id: NetworkTableClientId,
+        entries: MushroomTable,
+        removed: Vec<MushroomPath>,
+    ) -> Self {
+        Self {
+            client_id,
+            entries,
+            removed,
+        }
+    }
+
+    pub fn entries(&self) -> &MushroomTable {
+        &self.entries
+    }
+
+    pub fn removed(&self) -> &[MushroomPath] {
+        &self.removed
+    }
+}
+
+/// What a client was started with, enough to start it again
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct NetworkTableClientConfig {
+    pub id: NetworkTableClientId,
+    /// `None` for replays, they don't connect to anything
+    pub policy: Option<ConnectionPolicy>,
+}
+
 #[derive(Debug)]
 pub struct NetworkTableClient {
     id: NetworkTableClientId,
-    subscriptions: Sender<Vec<SubscriptionPackage>>,
-    input: Sender<MushroomTable>,
+    config: NetworkTableClientConfig,
+    subscriptions: Sender<SubscriptionRequest>,
+    subscribed: HashMap<String, SubscriptionInfo>,
+    input: Sender<PublishRequest>,
     output: SingleReceiver<MushroomTable>,
+    connection: ConnectionTracker,
+    topics: TopicCatalog,
+    history: TopicHistory,
+    recorder: NtRecorder,
+    metrics: MetricsTracker,
+    /// Whether the datalog entries for the metrics were started
+    metrics_logging: bool,
+    shutdown: watch::Sender<bool>,
+    coalesce: watch::Sender<Duration>,
+    playback: Option<PlaybackControl>,
     thread: TokioJoinHandle<()>,
 }
 impl NetworkTableClient {
-    fn new(
-        id: NetworkTableClientId,
-        subscriptions: Sender<Vec<SubscriptionPackage>>,
-        input: Sender<MushroomTable>,
-        output: SingleReceiver<MushroomTable>,
-        thread: TokioJoinHandle<()>,
-    ) -> Self {
-        Self {
-            id,
-            subscriptions,
-            input,
-            output,
-            thread,
+    /// Lets the nt4 task unpublish its topics before it exits
+    pub fn stop(&mut self) {
+        if self.shutdown.send(true).is_err() {
+            self.thread.abort();
         }
+        self.recorder.set_recording(false, None);
+        self.connection.transition(ConnectionState::Stopped);
     }
 
-    pub fn stop(&self) {
-        self.thread.abort();
+    pub fn config(&self) -> &NetworkTableClientConfig {
+        &self.config
     }
 
-    pub fn publish(&mut self, table: MushroomTable) {
-        tracing::info!("Publishing table to network table client {}", self.id);
-        self.input.try_send(table).unwrap_or_else(|err| {
+    pub fn connection_status(&self) -> ConnectionStatus {
+        self.connection.status()
+    }
+
+    pub fn list_topics(&self, prefix: Option<&str>) -> Vec<TopicInfo> {
+        self.topics.list(prefix)
+    }
+
+    /// Every sample of the topic at `path` newer than `since`, oldest first
+    pub fn history_since(
+        &self,
+        path: &MushroomPath,
+        since: Option<ServerTime>,
+    ) -> Vec<MushroomEntry> {
+        self.history.since(path, since)
+    }
+
+    pub fn set_history_config(&self, config: HistoryConfig) {
+        self.history.set_config(config);
+    }
+
+    /// Values received while recording get written to the session datalog
+    /// under `prefix`, the previous prefix is kept if none is given
+    pub fn set_recording(&mut self, recording: bool, prefix: Option<String>) {
+        tracing::info!("Recording of {} set to {}", self.id, recording);
+        self.recorder.set_recording(recording, prefix);
+    }
+
+    pub fn write_recording(&mut self) {
+        self.recorder.write();
+    }
+
+    /// `None` unless the client replays a datalog
+    pub fn playback(&self) -> Option<&PlaybackControl> {
+        self.playback.as_ref()
+    }
+
+    pub fn link_metrics(&self) -> LinkMetrics {
+        self.metrics.metrics()
+    }
+
+    /// Estimated from the last measured clock offset, zero until there is one
+    pub fn server_time(&self) -> ServerTime {
+        self.link_metrics()
+            .clock_offset
+            .map(|offset| ServerClock { offset }.server_now())
+            .unwrap_or_default()
+    }
+
+    /// Logs the metrics once per [`METRICS_INTERVAL`], main thread only
+    pub fn log_metrics(&mut self) -> Result<(), EnokiError> {
+        if let Some(metrics) = self.metrics.take_update() {
+            let prefix = format!("/NTClients/{}", self.id);
+            if !self.metrics_logging {
+                start_metrics_entries(&prefix)?;
+                self.metrics_logging = true;
+            }
+            log_metrics(&prefix, &metrics)?;
+        }
+        Ok(())
+    }
+
+    /// How long received values are collected before they are sent on together,
+    /// zero sends every value on its own
+    pub fn set_coalescing(&self, interval: Duration) {
+        self.coalesce.send(interval).unwrap_or_else(|err| {
             tracing::error!(
-                "Failed to publish to network table client {} because {}",
+                "Failed to set coalescing of network table client {} because {}",
                 self.id,
                 err
             );
         });
     }
 
+    /// `properties` are only needed for topics that should not use the defaults,
+    /// changing them updates the topic on the server
+    pub fn publish(
+        &mut self,
+        mut table: MushroomTable,
+        properties: HashMap<String, TopicProperties>,
+    ) -> Result<(), EnokiError> {
+        tracing::info!("Publishing table to network table client {}", self.id);
+        table.set_timestamp(self.server_time());
+        self.send_publish_request(PublishRequest::Values { table, properties })
+    }
+
+    /// Only works for topics this client published
+    pub fn set_properties(
+        &mut self,
+        path: String,
+        properties: TopicProperties,
+    ) -> Result<(), EnokiError> {
+        tracing::info!("Setting properties of {} for {}", path, self.id);
+        self.send_publish_request(PublishRequest::SetProperties { path, properties })
+    }
+
+    pub fn unpublish(&mut self, paths: Vec<String>) -> Result<(), EnokiError> {
+        tracing::info!("Unpublishing {:?} from {}", paths, self.id);
+        self.send_publish_request(PublishRequest::Unpublish(paths))
+    }
+
+    fn send_publish_request(&mut self, request: PublishRequest) -> Result<(), EnokiError> {
+        self.input.try_send(request).map_err(|err| {
+            EnokiError::NTPublish(format!(
+                "Failed to publish to network table client {} because {}",
+                self.id, err
+            ))
+        })
+    }
+
     pub fn subscribe(&mut self, sub_data: Vec<SubscriptionPackage>) {
-        self.subscriptions.try_send(sub_data).unwrap_or_else(|err| {
-            tracing::error!(
+        let infos: Vec<SubscriptionInfo> = sub_data.iter().map(SubscriptionPackage::info).collect();
+        match self
+            .subscriptions
+            .try_send(SubscriptionRequest::Subscribe(sub_data))
+        {
+            Ok(_) => {
+                for info in infos {
+                    self.subscribed.insert(info.topic.clone(), info);
+                }
+            }
+            Err(err) => tracing::error!(
                 "Failed to subscrive to network table client {} because {}",
                 self.id,
                 err
-            );
-        });
+            ),
+        }
+    }
+
+    pub fn unsubscribe(&mut self, names: Vec<String>) {
+        for name in &names {
+            self.subscribed.remove(name);
+        }
+        self.subscriptions
+            .try_send(SubscriptionRequest::Unsubscribe(names))
+            .unwrap_or_else(|err| {
+                tracing::error!(
+                    "Failed to unsubscribe from network table client {} because {}",
+                    self.id,
+                    err
+                );
+            });
+    }
+
+    /// Sorted by topic
+    pub fn list_subscriptions(&self) -> Vec<SubscriptionInfo> {
+        let mut subscriptions: Vec<SubscriptionInfo> = self.subscribed.values().cloned().collect();
+        subscriptions.sort_by(|a, b| a.topic.cmp(&b.topic));
+        subscriptions
     }
 
     pub fn poll(&mut self) -> MushroomTable {
         self.output.latest().clone()
     }
+
+    /// Only what changed after version `since` instead of the whole table,
+    /// see [`MushroomTable::delta_since`]
+    pub fn poll_delta(&mut self, epoch: u64, since: u64) -> MushroomTableDelta {
+        self.output.latest().delta_since(epoch, since)
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct SubscriptionPackage {
     name: String,
     options: Option<SubscriptionOptions>,
@@ -129,162 +365,942 @@ impl SubscriptionPackage {
             options: Some(options),
         }
     }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn is_prefix(&self) -> bool {
+        self.options
+            .as_ref()
+            .and_then(|options| options.prefix)
+            .unwrap_or(false)
+    }
+
+    /// Whether values of the topic at `path` are sent for this subscription
+    pub fn covers(&self, path: &str) -> bool {
+        if self.is_prefix() {
+            path.starts_with(self.name.as_str())
+        } else {
+            path == self.name
+        }
+    }
+
+    fn info(&self) -> SubscriptionInfo {
+        let options = self.options.clone().unwrap_or_default();
+        SubscriptionInfo {
+            topic: self.name.clone(),
+            periodic: options.periodic,
+            all: options.all,
+            prefix: options.prefix,
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct SubscriptionInfo {
+    topic: String,
+    periodic: Option<f64>,
+    all: Option<bool>,
+    prefix: Option<bool>,
+}
+
+#[derive(Debug)]
+pub enum PublishRequest {
+    Values {
+        table: MushroomTable,
+        properties: HashMap<String, TopicProperties>,
+    },
+    SetProperties {
+        path: String,
+        properties: TopicProperties,
+    },
+    Unpublish(Vec<String>),
+}
+
+#[derive(Debug)]
+pub enum SubscriptionRequest {
+    Subscribe(Vec<SubscriptionPackage>),
+    Unsubscribe(Vec<String>),
+}
+
+/// Everything a client task receives from or sends to its [`NetworkTableClient`]
+pub struct ClientChannels {
+    pub subscriptions: Receiver<SubscriptionRequest>,
+    pub input: Receiver<PublishRequest>,
+    pub output: SingleUpdater<MushroomTable>,
+    pub shutdown: watch::Receiver<bool>,
+    pub coalesce: watch::Receiver<Duration>,
+    pub recording: RecordingSender,
+}
+
+/// The state a client task shares with its [`NetworkTableClient`]
+pub struct ClientTrackers {
+    pub connection: ConnectionTracker,
+    pub topics: TopicCatalog,
+    pub history: TopicHistory,
+    pub metrics: MetricsTracker,
 }
 
 pub fn start_nt4_client(
-    address: Ipv4Addr,
-    port: u16,
-    identity: String,
+    app_handle: AppHandle,
+    id: NetworkTableClientId,
+    policy: ConnectionPolicy,
 ) -> Result<NetworkTableClient, EnokiError> {
     check_if_main_thread()?;
+    policy.validate()?;
 
-    let (snd_pub, rec_pub) = channel::<MushroomTable>(255);
-    let (rec_sub, snd_sub) = single_channel(MushroomTable::new(0));
-    let (subscription_sender, subscription_receiver) = channel::<Vec<SubscriptionPackage>>(255);
-    let id = NetworkTableClientId {
-        ip: address.octets(),
-        port,
-        identity: identity.clone(),
+    let config = NetworkTableClientConfig {
+        id: id.clone(),
+        policy: Some(policy.clone()),
     };
-    let thread = nt4(
-        address,
-        port,
-        identity,
-        subscription_receiver,
-        rec_pub,
-        snd_sub,
+    Ok(start_client_task(
+        app_handle.clone(),
+        config,
+        None,
+        |trackers, channels| nt4(app_handle, id, policy, trackers, channels),
+    ))
+}
+
+/// Sets up the client side of a client and lets `spawn` start the task feeding it,
+/// live connections and replays look the same to everything reading a client
+pub fn start_client_task(
+    app_handle: AppHandle,
+    config: NetworkTableClientConfig,
+    playback: Option<PlaybackControl>,
+    spawn: impl FnOnce(ClientTrackers, ClientChannels) -> TokioJoinHandle<()>,
+) -> NetworkTableClient {
+    let id = config.id.clone();
+    let (snd_pub, rec_pub) = channel::<PublishRequest>(255);
+    let (rec_sub, snd_sub) = single_channel(MushroomTable::new(ServerTime::default()));
+    let (subscription_sender, subscription_receiver) = channel::<SubscriptionRequest>(255);
+    let connection = ConnectionTracker::new(id.clone(), app_handle.clone());
+    let topics = TopicCatalog::new(id.clone(), app_handle.clone());
+    let history = TopicHistory::new(HistoryConfig::default());
+    let (recorder, recording) = NtRecorder::new(format!("NT:{}", id));
+    let metrics = MetricsTracker::new();
+    let (shutdown_sender, shutdown_receiver) = watch::channel(false);
+    let (coalesce_sender, coalesce_receiver) = watch::channel(DEFAULT_COALESCE_INTERVAL);
+    let thread = spawn(
+        ClientTrackers {
+            connection: connection.clone(),
+            topics: topics.clone(),
+            history: history.clone(),
+            metrics: metrics.clone(),
+        },
+        ClientChannels {
+            subscriptions: subscription_receiver,
+            input: rec_pub,
+            output: snd_sub,
+            shutdown: shutdown_receiver,
+            coalesce: coalesce_receiver,
+            recording,
+        },
     );
-    let client = NetworkTableClient::new(id, subscription_sender, snd_pub, rec_sub, thread);
+    NetworkTableClient {
+        id,
+        config,
+        subscriptions: subscription_sender,
+        subscribed: HashMap::new(),
+        input: snd_pub,
+        output: rec_sub,
+        connection,
+        topics,
+        history,
+        recorder,
+        metrics,
+        metrics_logging: false,
+        shutdown: shutdown_sender,
+        coalesce: coalesce_sender,
+        playback,
+        thread,
+    }
+}
+
+/// Roughly one frame, values of topics that update faster get batched
+const DEFAULT_COALESCE_INTERVAL: Duration = Duration::from_millis(15);
+
+/// Reconnecting is left to the nt4 task so the address gets resolved again,
+/// `disconnected` is notified when the current connection is lost
+fn nt4_config(
+    connection: &ConnectionTracker,
+    topics: &TopicCatalog,
+    policy: &ConnectionPolicy,
+    disconnected: Arc<Notify>,
+) -> Config {
+    let lost_state = if policy.reconnect {
+        ConnectionState::Reconnecting
+    } else {
+        ConnectionState::Failed
+    };
+    let on_disconnect_connection = connection.clone();
+    let on_reconnect_connection = connection.clone();
+    let on_announce_topics = topics.clone();
+    let on_un_announce_topics = topics.clone();
+    Config {
+        connect_timeout: policy.connect_timeout_ms,
+        disconnect_retry_interval: policy.retry_interval_ms,
+        should_reconnect: Box::new(|_| false),
+        on_announce: Box::new(move |topic| {
+            tracing::info!("Announced {}", topic.name);
+            on_announce_topics.announce(topic);
+            Box::pin(async {})
+        }),
+        on_un_announce: Box::new(move |topic| {
+            if let Some(topic) = topic {
+                tracing::info!("Un-announced {}", topic.name);
+                on_un_announce_topics.unannounce(&topic.name);
+            }
+            Box::pin(async {})
+        }),
+        on_disconnect: Box::new(move || {
+            let connection = on_disconnect_connection.clone();
+            let disconnected = disconnected.clone();
+            Box::pin(async move {
+                tracing::info!("Disconnected");
+                connection.transition(lost_state);
+                disconnected.notify_one();
+            })
+        }),
+        on_reconnect: Box::new(move || {
+            let connection = on_reconnect_connection.clone();
+            Box::pin(async move {
+                tracing::info!("Reconnected");
+                connection.transition(ConnectionState::Connected);
+            })
+        }),
+    }
+}
 
-    Ok(client)
+async fn resolve_nt4_address(id: &NetworkTableClientId) -> Result<SocketAddr, EnokiError> {
+    if let Some(team) = id.team {
+        return discover_team_address(team, id.port).await;
+    }
+    lookup_host((id.host.as_str(), id.port))
+        .await
+        .map_err(|err| EnokiError::NTConnect(format!("{}: {}", id.host, err)))?
+        .next()
+        .ok_or_else(|| EnokiError::NTConnect(format!("{}: no addresses found", id.host)))
 }
 
-fn nt4(
-    address: Ipv4Addr,
-    port: u16,
-    identity: String,
-    mut subscriptions: Receiver<Vec<SubscriptionPackage>>,
-    mut input: Receiver<MushroomTable>,
+async fn try_connect_nt4(
+    id: &NetworkTableClientId,
+    connection: &ConnectionTracker,
+    topics: &TopicCatalog,
+    policy: &ConnectionPolicy,
+    disconnected: Arc<Notify>,
+) -> Result<Client, EnokiError> {
+    let address = resolve_nt4_address(id).await?;
+    connection.set_address(address.to_string());
+    Client::try_new_w_config(
+        address,
+        nt4_config(connection, topics, policy, disconnected),
+        id.identity.clone(),
+    )
+    .await
+    .map_err(|err| EnokiError::NTConnect(format!("{}: {}", address, err)))
+}
+
+/// Keeps trying to connect until it succeeds or `policy` runs out of attempts,
+/// every failure is reported, `state` is what the client reports while trying
+/// and it only becomes `Failed` once there are no attempts left.
+/// Returns `None` if the client was stopped or gave up first
+async fn connect_nt4(
+    id: &NetworkTableClientId,
+    connection: &ConnectionTracker,
+    topics: &TopicCatalog,
+    policy: &ConnectionPolicy,
+    disconnected: &Arc<Notify>,
+    state: ConnectionState,
+    shutdown: &mut watch::Receiver<bool>,
+) -> Option<Client> {
+    let mut failures = 0;
+    loop {
+        if *shutdown.borrow() {
+            return None;
+        }
+        connection.transition(state);
+        match try_connect_nt4(id, connection, topics, policy, disconnected.clone()).await {
+            Ok(client) => {
+                connection.transition(ConnectionState::Connected);
+                return Some(client);
+            }
+            Err(err) => {
+                connection.report_error(err);
+                failures += 1;
+                if !policy.may_retry(failures) {
+                    connection.transition(ConnectionState::Failed);
+                    connection.report_error(EnokiError::NTConnect(format!(
+                        "giving up after {} attempts",
+                        failures
+                    )));
+                    return None;
+                }
+                //waits out the retry delay unless the client gets stopped
+                if tokio::time::timeout(policy.retry_delay(failures), shutdown.changed())
+                    .await
+                    .is_ok()
+                {
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+/// Announcements only get sent for subscribed topics,
+/// this fills the topic catalog without sending any values
+fn catalog_subscription() -> SubscriptionPackage {
+    SubscriptionPackage::new(
+        String::new(),
+        SubscriptionOptions {
+            prefix: Some(true),
+            topics_only: Some(true),
+            ..Default::default()
+        },
+    )
+}
+
+/// Struct and protobuf values can only be decoded with the schemas of their types
+fn schema_subscription() -> SubscriptionPackage {
+    SubscriptionPackage::new(
+        String::from(SCHEMA_PREFIX),
+        SubscriptionOptions {
+            prefix: Some(true),
+            all: Some(true),
+            ..Default::default()
+        },
+    )
+}
+
+async fn subscribe_nt4(
+    client: &Client,
+    connection: &ConnectionTracker,
+    sub_data: &SubscriptionPackage,
+) -> Option<Subscription> {
+    client
+        .subscribe_w_options(
+            std::slice::from_ref(&sub_data.name),
+            sub_data.options.clone(),
+        )
+        .await
+        .map_err(|err| {
+            connection.report_error(EnokiError::NTSubscribe(format!(
+                "{}: {}",
+                sub_data.name, err
+            )))
+        })
+        .ok()
+}
+
+/// A topic published by the dashboard and the type it was published with
+struct Publisher {
+    topic: PublishedTopic,
+    topic_type: Type,
+}
+
+/// Publishes the topic of the entry if needed before publishing its value,
+/// returns whether the value was sent
+async fn publish_nt4(
+    client: &Client,
+    connection: &ConnectionTracker,
+    pubs: &mut HashMap<String, Publisher>,
+    metrics: &MetricsTracker,
+    entry: &MushroomEntry,
+    properties: Option<&TopicProperties>,
+) -> bool {
+    let path = String::from(entry.get_path());
+    let topic_type = Type::from(entry.get_value());
+    if pubs
+        .get(&path)
+        .map_or(false, |publisher| publisher.topic_type != topic_type)
+    {
+        //a topic keeps the type it was published with
+        tracing::info!("Type of {} changed, publishing it again", path);
+        unpublish_nt4(client, pubs, &path).await;
+    }
+    if !pubs.contains_key(&path) {
+        match client
+            .publish_topic(
+                path.as_str(),
+                Type::from(entry.get_value()),
+                properties.cloned().map(PublishProperties::from),
+            )
+            .await
+        {
+            Ok(topic) => {
+                pubs.insert(path.clone(), Publisher { topic, topic_type });
+            }
+            Err(err) => {
+                connection.report_error(EnokiError::NTPublish(format!("{}: {}", path, err)));
+                return false;
+            }
+        }
+    }
+    let topic = &pubs.get(&path).unwrap().topic;
+    let value = rmpv::Value::from(entry.get_value());
+    if let Err(err) = client.publish_value(topic, &value).await {
+        connection.report_error(EnokiError::NTPublish(format!("{}: {}", path, err)));
+        return false;
+    }
+    metrics.count_out(encoded_len(&value));
+    true
+}
+
+/// Changes the properties of a topic this client publishes without giving up its publisher,
+/// returns whether the update was sent
+async fn set_properties_nt4(
+    client: &Client,
+    connection: &ConnectionTracker,
+    pubs: &HashMap<String, Publisher>,
+    path: &str,
+    properties: &TopicProperties,
+) -> bool {
+    let publisher = match pubs.get(path) {
+        Some(publisher) => publisher,
+        None => return false,
+    };
+    if let Err(err) = client
+        .set_properties(&publisher.topic, properties.update())
+        .await
+    {
+        connection.report_error(EnokiError::NTPublish(format!("{}: {}", path, err)));
+        return false;
+    }
+    true
+}
+
+/// Returns whether the topic was published
+async fn unpublish_nt4(client: &Client, pubs: &mut HashMap<String, Publisher>, path: &str) -> bool {
+    match pubs.remove(path) {
+        Some(publisher) => {
+            client.unpublish(publisher.topic).await.ok();
+            true
+        }
+        None => false,
+    }
+}
+
+/// Whether any of the subscriptions a client asked for covers the topic at `path`
+pub fn is_subscribed<'a>(
+    subscriptions: impl IntoIterator<Item = &'a SubscriptionPackage>,
+    path: &str,
+) -> bool {
+    subscriptions.into_iter().any(|sub| sub.covers(path))
+}
+
+/// A value as it came off the wire, decoding it needs the type of its topic
+struct ReceivedValue {
+    topic: String,
+    topic_type: Type,
+    data: rmpv::Value,
+    timestamp: ServerTime,
+    /// Size on the wire
+    bytes: usize,
+}
+
+impl ReceivedValue {
+    fn new(client: &Client, msg: MessageData) -> Self {
+        Self {
+            bytes: encoded_len(&msg.data),
+            timestamp: ServerTime(client.to_real_time(msg.timestamp) as u64),
+            topic: msg.topic_name,
+            topic_type: msg.r#type,
+            data: msg.data,
+        }
+    }
+}
+
+/// Owns a subscription and forwards its values to the nt4 task,
+/// the subscription is only polled by this task so no value is dropped between polls
+struct SubscriptionForwarder {
+    stop: oneshot::Sender<()>,
+    task: TokioJoinHandle<Option<Subscription>>,
+}
+
+impl SubscriptionForwarder {
+    fn start(mut sub: Subscription, values: UnboundedSender<MessageData>) -> Self {
+        let (stop, mut stopped) = oneshot::channel();
+        let task = tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = &mut stopped => return Some(sub),
+                    msg = sub.next() => match msg {
+                        Some(msg) => {
+                            if values.send(msg).is_err() {
+                                return Some(sub);
+                            }
+                        }
+                        //the subscription is gone, the disconnect handling replaces it
+                        None => return None,
+                    },
+                }
+            }
+        });
+        Self { stop, task }
+    }
+
+    /// Hands the subscription back so it can be unsubscribed
+    async fn stop(self) -> Option<Subscription> {
+        self.stop.send(()).ok();
+        self.task.await.ok().flatten()
+    }
+}
+
+/// When values received since the last flush are due to be flushed,
+/// the first value after a flush starts the interval
+#[derive(Debug, Default)]
+pub struct FlushTimer {
+    at: Option<Instant>,
+}
+
+impl FlushTimer {
+    /// Returns true if the value has to be flushed right away
+    pub fn received(&mut self, interval: Duration, now: Instant) -> bool {
+        if interval.is_zero() {
+            return true;
+        }
+        self.at.get_or_insert(now + interval);
+        false
+    }
+
+    pub fn at(&self) -> Option<Instant> {
+        self.at
+    }
+
+    pub fn flushed(&mut self) {
+        self.at = None;
+    }
+}
+
+/// The state of an nt4 task, the requests it has to redo after a reconnect
+/// and the values it received since the last flush
+struct Nt4State {
+    app_handle: AppHandle,
+    id: NetworkTableClientId,
+    connection: ConnectionTracker,
+    topics: TopicCatalog,
+    history: TopicHistory,
+    recording: RecordingSender,
+    metrics: MetricsTracker,
     output: SingleUpdater<MushroomTable>,
+    policy: ConnectionPolicy,
+    disconnected: Arc<Notify>,
+    client: Client,
+    _catalog_sub: Option<Subscription>,
+    schema_sub: Option<SubscriptionForwarder>,
+    /// Values of every subscription, see [`SubscriptionForwarder`]
+    values: UnboundedSender<MessageData>,
+    schemas: SchemaRegistry,
+    /// The topic of every struct field entry in the table
+    field_topics: HashMap<MushroomPath, String>,
+    subs: HashMap<String, SubscriptionForwarder>,
+    sub_requests: HashMap<String, SubscriptionPackage>,
+    pubs: HashMap<String, Publisher>,
+    published: MushroomTable,
+    pub_properties: HashMap<String, TopicProperties>,
+    table: MushroomTable,
+    pending: MushroomTable,
+    removed: Vec<MushroomPath>,
+}
+
+impl Nt4State {
+    /// Returns false if the client was stopped before it could reconnect
+    async fn reconnect(&mut self, shutdown: &mut watch::Receiver<bool>) -> bool {
+        self.topics.clear();
+        self.client = match connect_nt4(
+            &self.id,
+            &self.connection,
+            &self.topics,
+            &self.policy,
+            &self.disconnected,
+            ConnectionState::Reconnecting,
+            shutdown,
+        )
+        .await
+        {
+            Some(client) => client,
+            None => return false,
+        };
+        //handles from the old connection are dead, dropping their forwarders stops them
+        self.subs.clear();
+        self.pubs.clear();
+        self.metrics.reset_link();
+        self.sync_metrics();
+        self._catalog_sub =
+            subscribe_nt4(&self.client, &self.connection, &catalog_subscription()).await;
+        self.schema_sub = subscribe_nt4(&self.client, &self.connection, &schema_subscription())
+            .await
+            .map(|sub| SubscriptionForwarder::start(sub, self.values.clone()));
+        for sub_data in self.sub_requests.values() {
+            if let Some(sub) = subscribe_nt4(&self.client, &self.connection, sub_data).await {
+                let forwarder = SubscriptionForwarder::start(sub, self.values.clone());
+                self.subs.insert(sub_data.name.clone(), forwarder);
+            }
+        }
+        for entry in self.published.get_entries() {
+            let properties = self.pub_properties.get(&String::from(entry.get_path()));
+            publish_nt4(
+                &self.client,
+                &self.connection,
+                &mut self.pubs,
+                &self.metrics,
+                entry,
+                properties,
+            )
+            .await;
+        }
+        true
+    }
+
+    async fn unsubscribe(&mut self, name: &str) {
+        if let Some(forwarder) = self.subs.remove(name) {
+            if let Some(sub) = forwarder.stop().await {
+                self.client.unsubscribe(sub).await.ok();
+            }
+        }
+    }
+
+    async fn handle_subscription(&mut self, request: SubscriptionRequest) {
+        match request {
+            SubscriptionRequest::Subscribe(new_sub_data) => {
+                for sub_data in new_sub_data {
+                    let name = sub_data.name.clone();
+                    self.unsubscribe(&name).await;
+                    if let Some(sub) =
+                        subscribe_nt4(&self.client, &self.connection, &sub_data).await
+                    {
+                        let forwarder = SubscriptionForwarder::start(sub, self.values.clone());
+                        self.subs.insert(name.clone(), forwarder);
+                        tracing::info!("Subscribed to {}:{}", self.id, name);
+                    }
+                    self.sub_requests.insert(name, sub_data);
+                }
+            }
+            SubscriptionRequest::Unsubscribe(names) => {
+                for name in names {
+                    self.unsubscribe(&name).await;
+                    self.sub_requests.remove(&name);
+                    tracing::info!("Unsubscribed from {}:{}", self.id, name);
+                }
+                //values stay around while any other subscription still covers them
+                let stale: Vec<MushroomPath> = self
+                    .table
+                    .get_entries()
+                    .iter()
+                    .map(MushroomEntry::get_path)
+                    .filter(|path| {
+                        //struct fields go with the topic they came from
+                        let path = match self.field_topics.get(path) {
+                            Some(topic) => topic.clone(),
+                            None => String::from(path.clone()),
+                        };
+                        !is_subscribed(self.sub_requests.values(), &path)
+                    })
+                    .collect();
+                for path in stale {
+                    self.field_topics.remove(&path);
+                    self.table.remove_entry(&path);
+                    self.pending.remove_entry(&path);
+                    self.history.remove(&path);
+                    self.removed.push(path);
+                }
+            }
+        }
+    }
+
+    async fn handle_publish(&mut self, request: PublishRequest) {
+        match request {
+            PublishRequest::Values { table, properties } => {
+                for entry in table.get_entries() {
+                    let path = String::from(entry.get_path());
+                    if let Some(properties) = properties.get(&path) {
+                        if self.pub_properties.get(&path) != Some(properties) {
+                            //topics that aren't published yet get them with the publish
+                            set_properties_nt4(
+                                &self.client,
+                                &self.connection,
+                                &self.pubs,
+                                &path,
+                                properties,
+                            )
+                            .await;
+                            self.pub_properties.insert(path.clone(), properties.clone());
+                        }
+                    }
+                    let properties = self.pub_properties.get(&path);
+                    if publish_nt4(
+                        &self.client,
+                        &self.connection,
+                        &mut self.pubs,
+                        &self.metrics,
+                        entry,
+                        properties,
+                    )
+                    .await
+                    {
+                        tracing::info!("Published to {}:{}", self.id, path);
+                    }
+                }
+                self.published.update_entries(&table);
+            }
+            PublishRequest::SetProperties { path, properties } => {
+                match self.published.get_entry(&path.as_str().into()) {
+                    Some(_) => {
+                        if set_properties_nt4(
+                            &self.client,
+                            &self.connection,
+                            &self.pubs,
+                            &path,
+                            &properties,
+                        )
+                        .await
+                        {
+                            tracing::info!("Set properties of {}:{}", self.id, path);
+                        }
+                        //a topic published later, e.g. after a reconnect, gets them with the publish
+                        self.pub_properties.insert(path, properties);
+                    }
+                    None => self.connection.report_error(EnokiError::NTPublish(format!(
+                        "{}: properties can only be set on topics this client published",
+                        path
+                    ))),
+                }
+            }
+            PublishRequest::Unpublish(paths) => {
+                for path in paths {
+                    if unpublish_nt4(&self.client, &mut self.pubs, &path).await {
+                        tracing::info!("Unpublished {}:{}", self.id, path);
+                    }
+                    //so it doesn't come back after a reconnect
+                    self.published.remove_entry(&path.as_str().into());
+                    self.pub_properties.remove(&path);
+                }
+            }
+        }
+    }
+
+    /// Values that don't match the type of their topic are reported and dropped,
+    /// values of topics the client unsubscribed from are just dropped
+    fn receive(&mut self, received: ReceivedValue) {
+        self.metrics.count_in(received.bytes);
+        let path = received.topic;
+        let subscribed = is_subscribed(self.sub_requests.values(), &path);
+        //values queued before an unsubscribe would bring the topic back
+        if !subscribed && !path.starts_with(SCHEMA_PREFIX) {
+            return;
+        }
+        //the announced type string also carries struct and protobuf type names
+        let topic_type = self
+            .topics
+            .topic_type(&path)
+            .unwrap_or_else(|| type_string(&received.topic_type));
+        let value = match MushroomValue::try_from((topic_type.as_str(), received.data)) {
+            Ok(value) => value,
+            Err(err) => {
+                self.connection
+                    .report_error(EnokiError::NTDecode(format!("{}: {}", path, err)));
+                return;
+            }
+        };
+        let entry = MushroomEntry::new(value, path.as_str().into(), Some(received.timestamp));
+        let clock = self.clock();
+        //schemas also come in for the internal schema subscription
+        if !subscribed {
+            self.recording.record(&entry, &clock);
+            expand_entry(&mut self.schemas, entry, None);
+            return;
+        }
+        let entries = expand_entry(&mut self.schemas, entry, Some(&topic_type));
+        for (index, entry) in entries.into_iter().enumerate() {
+            //struct values keep their raw bytes, their fields are recorded next to them
+            self.recording.record(&entry, &clock);
+            if index > 0 {
+                self.field_topics.insert(entry.get_path(), path.clone());
+            }
+            self.history.record(&entry);
+            self.pending.add_entry(entry);
+        }
+    }
+
+    fn server_time(&self) -> ServerTime {
+        ServerTime(self.client.real_server_time() as u64)
+    }
+
+    fn clock(&self) -> ServerClock {
+        ServerClock::measure(self.server_time())
+    }
+
+    /// Takes the round trip and clock offset from the time sync of the connection
+    fn sync_metrics(&self) {
+        self.metrics
+            .record_time_sync(self.client.rtt(), self.clock());
+    }
+
+    /// Sends everything received since the last flush to the frontend and the client
+    fn flush(&mut self) {
+        if self.pending.is_empty() && self.removed.is_empty() {
+            return;
+        }
+        //use client timestamp
+        let server_time = self.server_time();
+        let new_table_data = std::mem::replace(&mut self.pending, MushroomTable::new(server_time));
+        self.table.update_all(&new_table_data);
+        self.app_handle
+            .emit_all(
+                NT_UPDATE_EVENT,
+                NetworkTableUpdate::new(
+                    self.id.clone(),
+                    new_table_data,
+                    std::mem::take(&mut self.removed),
+                ),
+            )
+            .unwrap_or_else(|err| {
+                tracing::error!("Failed to emit update for {} because {}", self.id, err);
+            });
+        self.output
+            .update(self.table.clone())
+            .unwrap_or_else(|err| {
+                tracing::error!("Failed to send to network table client {}", self.id);
+                tracing::error!("Error: {}", err);
+            });
+    }
+
+    async fn stop(&mut self) {
+        let paths: Vec<String> = self.pubs.keys().cloned().collect();
+        for path in paths {
+            unpublish_nt4(&self.client, &mut self.pubs, &path).await;
+        }
+        //a connection that finished after the stop may have reported itself
+        self.connection.transition(ConnectionState::Stopped);
+        tracing::info!("Network table client {} stopped", self.id);
+    }
+}
+
+fn nt4(
+    app_handle: AppHandle,
+    id: NetworkTableClientId,
+    policy: ConnectionPolicy,
+    trackers: ClientTrackers,
+    channels: ClientChannels,
 ) -> TokioJoinHandle<()> {
+    let ClientTrackers {
+        connection,
+        topics,
+        history,
+        metrics,
+    } = trackers;
+    let ClientChannels {
+        mut subscriptions,
+        mut input,
+        output,
+        mut shutdown,
+        coalesce,
+        recording,
+    } = channels;
     //error handling is in the thread
     THREAD_POOL.with(|thread_pool| {
         thread_pool.borrow().as_ref().unwrap().spawn(async move {
-            let mut subs: HashMap<String, Subscription> = HashMap::new();
-            let mut pubs: HashMap<String, PublishedTopic> = HashMap::new();
-
-            let client = Client::try_new_w_config(
-                SocketAddrV4::new(address, port),
-                Config {
-                    connect_timeout: 30000,
-                    disconnect_retry_interval: 10000,
-                    should_reconnect: Box::new(default_should_reconnect),
-                    on_announce: Box::new(|_| {
-                        Box::pin(async {
-                            tracing::info!("Announced");
-                        })
-                    }),
-                    on_un_announce: Box::new(|_| {
-                        Box::pin(async {
-                            tracing::info!("Un-announced");
-                        })
-                    }),
-                    on_disconnect: Box::new(|| {
-                        Box::pin(async {
-                            tracing::info!("Disconnected");
-                        })
-                    }),
-                    on_reconnect: Box::new(|| {
-                        Box::pin(async {
-                            tracing::info!("Reconnected");
-                        })
-                    }),
-                },
-                identity,
+            let disconnected = Arc::new(Notify::new());
+            let client = match connect_nt4(
+                &id,
+                &connection,
+                &topics,
+                &policy,
+                &disconnected,
+                ConnectionState::Connecting,
+                &mut shutdown,
             )
             .await
-            .unwrap_or_else(|err| {
-                tracing::error!("Failed to connect to {}:{} because {}", address, port, err);
-                panic!();
-            });
+            {
+                Some(client) => client,
+                None => return,
+            };
+            let catalog_sub = subscribe_nt4(&client, &connection, &catalog_subscription()).await;
+            let (values, mut received) = unbounded_channel();
+            let schema_sub = subscribe_nt4(&client, &connection, &schema_subscription())
+                .await
+                .map(|sub| SubscriptionForwarder::start(sub, values.clone()));
 
-            let mut table = MushroomTable::new(client.real_server_time());
+            let mut state = Nt4State {
+                app_handle,
+                id,
+                connection,
+                topics,
+                history,
+                recording,
+                metrics,
+                output,
+                policy,
+                disconnected: disconnected.clone(),
+                table: MushroomTable::new(ServerTime(client.real_server_time() as u64)),
+                pending: MushroomTable::new(ServerTime(client.real_server_time() as u64)),
+                client,
+                _catalog_sub: catalog_sub,
+                schema_sub,
+                values,
+                schemas: SchemaRegistry::new(),
+                field_topics: HashMap::new(),
+                subs: HashMap::new(),
+                sub_requests: HashMap::new(),
+                pubs: HashMap::new(),
+                published: MushroomTable::new(ServerTime::default()),
+                pub_properties: HashMap::new(),
+                removed: Vec::new(),
+            };
+            state.sync_metrics();
+            let mut flush_timer = FlushTimer::default();
+            let mut metrics_interval = tokio::time::interval(METRICS_INTERVAL);
 
             loop {
-                let start_time = std::time::Instant::now();
-
-                let new_sub_data = subscriptions.try_recv();
-                if let Ok(new_sub_data) = new_sub_data {
-                    for sub_data in new_sub_data {
-                        let name = sub_data.name.clone();
-                        let options = sub_data.options.clone();
-                        if subs.contains_key(&name) {
-                            client.unsubscribe(subs.remove(&name).unwrap()).await.ok();
+                tokio::select! {
+                    //a dropped client stops the task as well
+                    _ = shutdown.changed() => break,
+                    _ = disconnected.notified() => {
+                        //the disconnect already left the client failed
+                        if !state.policy.reconnect {
+                            return;
+                        }
+                        if !state.reconnect(&mut shutdown).await {
+                            return;
                         }
-                        let sub = client
-                            .subscribe_w_options(&[name.clone()], options)
-                            .await
-                            .unwrap_or_else(|err| {
-                                tracing::error!("Failed to subscribe to {}:{}", address, port);
-                                tracing::error!("Error: {}", err);
-                                panic!();
-                            });
-                        subs.insert(name.clone(), sub);
-                        tracing::info!("Subscribed to {}:{}:{}", address, port, name);
                     }
-                }
-
-                let new_pub_data = input.try_recv();
-                if let Ok(table) = new_pub_data {
-                    for entry in table.get_entries() {
-                        let path = String::from(entry.get_path());
-                        if !pubs.contains_key(&path) {
-                            let topic = client
-                                .publish_topic(path.as_str(), Type::from(entry.get_value()), None)
-                                .await
-                                .unwrap();
-                            pubs.insert(path.clone(), topic);
+                    request = subscriptions.recv() => match request {
+                        Some(request) => {
+                            state.handle_subscription(request).await;
+                            //removals are not worth waiting for
+                            if !state.removed.is_empty() {
+                                state.flush();
+                                flush_timer.flushed();
+                            }
+                        }
+                        None => break,
+                    },
+                    request = input.recv() => match request {
+                        Some(request) => state.handle_publish(request).await,
+                        None => break,
+                    },
+                    //the state keeps a sender so this never ends
+                    Some(msg) = received.recv() => {
+                        state.receive(ReceivedValue::new(&state.client, msg));
+                        if flush_timer.received(*coalesce.borrow(), Instant::now()) {
+                            state.flush();
+                            flush_timer.flushed();
                         }
-                        let topic = pubs.get(&path).unwrap();
-                        client
-                            .publish_value(topic, &rmpv::Value::from(entry.get_value()))
-                            .await
-                            .ok();
-                        tracing::info!("Published to {}:{}:{}", address, port, path);
                     }
-                }
-
-                //use client timestamp
-                let mut new_table_data: MushroomTable =
-                    MushroomTable::new(client.real_server_time());
-                for sub in subs.values_mut() {
-                    while let Ok(msg) = sub.try_next().await {
-                        let entry = MushroomEntry::new(
-                            msg.data.into(),
-                            msg.topic_name.into(),
-                            Some(client.to_real_time(msg.timestamp) as f64),
-                        );
-                        new_table_data.add_entry(entry);
+                    _ = metrics_interval.tick() => {
+                        state.sync_metrics();
+                        state.metrics.roll();
+                    }
+                    _ = tokio::time::sleep_until(flush_timer.at().unwrap_or_else(Instant::now)),
+                        if flush_timer.at().is_some() =>
+                    {
+                        state.flush();
+                        flush_timer.flushed();
                     }
                 }
-                table.update_all(&new_table_data);
-                output.update(table.clone()).unwrap_or_else(|err| {
-                    tracing::error!(
-                        "Failed to send to network table client {}:{}",
-                        address,
-                        port
-                    );
-                    tracing::error!("Error: {}", err);
-                });
-
-                let elapsed = start_time.elapsed();
-                tokio::time::sleep(Duration::from_secs_f64(
-                    (Duration::from_millis(15) - elapsed)
-                        .as_secs_f64()
-                        .clamp(0.0, 0.015),
-                ))
-                .await;
             }
+            state.stop().await;
         })
     })
 }
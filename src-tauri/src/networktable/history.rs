@@ -0,0 +1,107 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use crate::mushroom_types::{MushroomEntry, MushroomPath};
+use crate::time::ServerTime;
+
+/// How many samples are kept per topic, whichever limit is hit first applies
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct HistoryConfig {
+    pub depth: usize,
+    /// Microseconds of [`ServerTime`] before the newest sample
+    pub window: Option<u64>,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            depth: 1024,
+            window: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct HistoryBuffers {
+    config: HistoryConfig,
+    samples: HashMap<MushroomPath, VecDeque<MushroomEntry>>,
+}
+
+impl HistoryBuffers {
+    fn trim(config: &HistoryConfig, samples: &mut VecDeque<MushroomEntry>) {
+        while samples.len() > config.depth {
+            samples.pop_front();
+        }
+        let newest = samples.back().and_then(MushroomEntry::get_timestamp);
+        if let (Some(window), Some(newest)) = (config.window, newest) {
+            let oldest = newest.0.saturating_sub(window);
+            while samples
+                .front()
+                .and_then(MushroomEntry::get_timestamp)
+                .map_or(false, |timestamp| timestamp.0 < oldest)
+            {
+                samples.pop_front();
+            }
+        }
+    }
+}
+
+/// Every sample the nt4 task received per topic, bounded by a [`HistoryConfig`],
+/// every clone points at the same buffers
+#[derive(Debug, Clone)]
+pub struct TopicHistory {
+    buffers: Arc<Mutex<HistoryBuffers>>,
+}
+
+impl TopicHistory {
+    pub fn new(config: HistoryConfig) -> Self {
+        Self {
+            buffers: Arc::new(Mutex::new(HistoryBuffers {
+                config,
+                samples: HashMap::new(),
+            })),
+        }
+    }
+
+    /// Existing buffers get trimmed to the new limits right away
+    pub fn set_config(&self, config: HistoryConfig) {
+        let mut buffers = self.buffers.lock().unwrap();
+        buffers.config = config;
+        for samples in buffers.samples.values_mut() {
+            HistoryBuffers::trim(&config, samples);
+        }
+    }
+
+    /// Entries without a timestamp can't be queried and are ignored
+    pub fn record(&self, entry: &MushroomEntry) {
+        if entry.get_timestamp().is_none() {
+            return;
+        }
+        let mut buffers = self.buffers.lock().unwrap();
+        let config = buffers.config;
+        let samples = buffers.samples.entry(entry.get_path()).or_default();
+        samples.push_back(entry.clone());
+        HistoryBuffers::trim(&config, samples);
+    }
+
+    /// Oldest first, only samples newer than `since` if given
+    pub fn since(&self, path: &MushroomPath, since: Option<ServerTime>) -> Vec<MushroomEntry> {
+        self.buffers
+            .lock()
+            .unwrap()
+            .samples
+            .get(path)
+            .map(|samples| {
+                samples
+                    .iter()
+                    .filter(|entry| entry.get_timestamp() > since)
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn remove(&self, path: &MushroomPath) {
+        self.buffers.lock().unwrap().samples.remove(path);
+    }
+}
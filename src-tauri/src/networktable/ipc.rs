@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+use tauri::http::{Request, Response, ResponseBuilder};
+use tauri::{AppHandle, Runtime};
+use url::Url;
+
+use crate::{check_if_main_thread, NETWORK_CLIENT_MAP};
+
+use super::handler::NetworkTableClientId;
+
+/// Table snapshots and deltas can also be fetched from this uri scheme, which unlike
+/// commands can answer with msgpack, e.g.
+/// `enoki://localhost/delta?client=<url encoded client id json>&epoch=3&since=12&format=msgpack`
+pub const IPC_SCHEME: &str = "enoki";
+
+/// Where the app's own pages are served from, other origins get no cors headers
+const APP_ORIGINS: [&str; 2] = ["tauri://localhost", "https://tauri.localhost"];
+
+/// The vite dev server of `devPath`
+const DEV_ORIGIN: &str = "http://localhost:5173";
+
+/// How the body of an ipc response is encoded, picked with the `format` parameter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpcFormat {
+    Json,
+    MessagePack,
+}
+
+impl IpcFormat {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "json" => Some(IpcFormat::Json),
+            "msgpack" => Some(IpcFormat::MessagePack),
+            _ => None,
+        }
+    }
+
+    pub fn mimetype(&self) -> &'static str {
+        match self {
+            IpcFormat::Json => "application/json",
+            IpcFormat::MessagePack => "application/msgpack",
+        }
+    }
+
+    /// Msgpack keeps the field names so both formats decode to the same objects
+    pub fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, String> {
+        match self {
+            IpcFormat::Json => serde_json::to_vec(value).map_err(|err| err.to_string()),
+            IpcFormat::MessagePack => rmp_serde::to_vec_named(value).map_err(|err| err.to_string()),
+        }
+    }
+}
+
+/// The route and the decoded query parameters of an ipc uri
+#[derive(Debug, Clone, PartialEq)]
+pub struct IpcQuery {
+    pub route: String,
+    pub params: HashMap<String, String>,
+}
+
+impl IpcQuery {
+    /// Webviews give either `enoki://localhost/route?..` or `https://enoki.localhost/route?..`
+    pub fn parse(uri: &str) -> Result<Self, String> {
+        let url = Url::parse(uri).map_err(|err| format!("invalid uri: {}", err))?;
+        Ok(Self {
+            route: String::from(url.path().trim_matches('/')),
+            params: url.query_pairs().into_owned().collect(),
+        })
+    }
+
+    pub fn format(&self) -> Result<IpcFormat, String> {
+        match self.params.get("format") {
+            Some(name) => IpcFormat::parse(name).ok_or_else(|| format!("unknown format {}", name)),
+            None => Ok(IpcFormat::Json),
+        }
+    }
+
+    pub fn client_id(&self) -> Result<NetworkTableClientId, String> {
+        let client = self.params.get("client").ok_or("missing client")?;
+        serde_json::from_str(client).map_err(|err| format!("invalid client: {}", err))
+    }
+}
+
+/// The origin to allow in cors headers, only the app itself may read the responses
+pub fn allowed_origin(origin: Option<&str>) -> Option<&str> {
+    origin.filter(|origin| {
+        APP_ORIGINS.contains(origin) || (cfg!(debug_assertions) && *origin == DEV_ORIGIN)
+    })
+}
+
+/// Serves `table` and `delta` like [`super::commands::get_subbed_entries_values`] and
+/// [`super::commands::get_subbed_entries_delta`] in the requested format
+pub fn handle_ipc_request<R: Runtime>(
+    _app_handle: &AppHandle<R>,
+    request: &Request,
+) -> Result<Response, Box<dyn std::error::Error>> {
+    let (status, mimetype, body) = match IpcQuery::parse(request.uri())
+        .map_err(|err| (400, err))
+        .and_then(|query| respond(&query))
+    {
+        Ok((format, body)) => (200, format.mimetype(), body),
+        Err((status, message)) => {
+            tracing::warn!("Failed ipc request {}: {}", request.uri(), message);
+            (status, "text/plain", message.into_bytes())
+        }
+    };
+    let origin = request
+        .headers()
+        .get("Origin")
+        .and_then(|origin| origin.to_str().ok());
+    let mut response = ResponseBuilder::new()
+        .status(status)
+        .mimetype(mimetype)
+        .header("Vary", "Origin");
+    if let Some(origin) = allowed_origin(origin) {
+        response = response.header("Access-Control-Allow-Origin", origin);
+    }
+    response.body(body)
+}
+
+fn respond(query: &IpcQuery) -> Result<(IpcFormat, Vec<u8>), (u16, String)> {
+    check_if_main_thread().map_err(|err| (500, err.to_string()))?;
+    let format = query.format().map_err(|err| (400, err))?;
+    let client_id = query.client_id().map_err(|err| (400, err))?;
+    let body = NETWORK_CLIENT_MAP.with(|map| {
+        let mut map = map.borrow_mut();
+        let client = map.get_mut(&client_id).ok_or_else(|| {
+            (
+                404,
+                format!("no network table client found for {}", client_id),
+            )
+        })?;
+        match query.route.as_str() {
+            "table" => format.encode(&client.poll()),
+            "delta" => {
+                let param = |name: &str| match query.params.get(name) {
+                    Some(value) => value
+                        .parse()
+                        .map_err(|_| (400, format!("invalid {} {}", name, value))),
+                    None => Ok(0),
+                };
+                format.encode(&client.poll_delta(param("epoch")?, param("since")?))
+            }
+            route => return Err((404, format!("unknown route {}", route))),
+        }
+        .map_err(|err| (500, err))
+    })?;
+    Ok((format, body))
+}
@@ -0,0 +1,206 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::datalog::handler::{log_datalog_value, start_datalog_entry};
+use crate::error::{log_result_consume, EnokiError};
+use crate::mushroom_types::MushroomValue;
+use crate::time::ServerClock;
+use crate::NETWORK_CLIENT_MAP;
+
+/// How often rates are computed and the round trip gets read
+pub const METRICS_INTERVAL: Duration = Duration::from_millis(1000);
+
+/// The datalog entries metrics get logged to, relative to a per-client prefix
+const METRICS_ENTRIES: [(&str, &str); 7] = [
+    ("RTT", "double"),
+    ("Jitter", "double"),
+    ("ClockOffset", "int64"),
+    ("MessagesIn", "double"),
+    ("MessagesOut", "double"),
+    ("BytesIn", "double"),
+    ("BytesOut", "double"),
+];
+
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize)]
+pub struct LinkMetrics {
+    /// Milliseconds an NT4 time sync message takes to the server and back
+    pub rtt: Option<f64>,
+    /// Milliseconds, the smoothed difference between consecutive round trips
+    pub jitter: Option<f64>,
+    /// Microseconds the server clock is ahead of the local [`crate::time::MonotonicTime`]
+    pub clock_offset: Option<i64>,
+    /// Per second, over the last [`METRICS_INTERVAL`]
+    pub messages_in: f64,
+    pub messages_out: f64,
+    pub bytes_in: f64,
+    pub bytes_out: f64,
+}
+
+impl LinkMetrics {
+    fn values(&self) -> [Option<MushroomValue>; 7] {
+        [
+            self.rtt.map(MushroomValue::Double),
+            self.jitter.map(MushroomValue::Double),
+            self.clock_offset.map(MushroomValue::Int),
+            Some(MushroomValue::Double(self.messages_in)),
+            Some(MushroomValue::Double(self.messages_out)),
+            Some(MushroomValue::Double(self.bytes_in)),
+            Some(MushroomValue::Double(self.bytes_out)),
+        ]
+    }
+}
+
+#[derive(Debug)]
+struct MetricsState {
+    metrics: LinkMetrics,
+    window_start: Instant,
+    messages_in: u64,
+    messages_out: u64,
+    bytes_in: u64,
+    bytes_out: u64,
+    /// Whether the metrics changed since they were last logged
+    updated: bool,
+}
+
+/// Shared between a [`super::handler::NetworkTableClient`] and its nt4 task,
+/// every clone points at the same metrics
+#[derive(Debug, Clone)]
+pub struct MetricsTracker {
+    state: Arc<Mutex<MetricsState>>,
+}
+
+impl Default for MetricsTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MetricsTracker {
+    pub fn new() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(MetricsState {
+                metrics: LinkMetrics::default(),
+                window_start: Instant::now(),
+                messages_in: 0,
+                messages_out: 0,
+                bytes_in: 0,
+                bytes_out: 0,
+                updated: false,
+            })),
+        }
+    }
+
+    pub fn metrics(&self) -> LinkMetrics {
+        self.state.lock().unwrap().metrics.clone()
+    }
+
+    pub fn count_in(&self, bytes: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.messages_in += 1;
+        state.bytes_in += bytes as u64;
+    }
+
+    pub fn count_out(&self, bytes: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.messages_out += 1;
+        state.bytes_out += bytes as u64;
+    }
+
+    /// Turns the counts since the last roll into rates
+    pub fn roll(&self) {
+        let mut state = self.state.lock().unwrap();
+        let elapsed = state.window_start.elapsed().as_secs_f64().max(f64::EPSILON);
+        state.metrics.messages_in = state.messages_in as f64 / elapsed;
+        state.metrics.messages_out = state.messages_out as f64 / elapsed;
+        state.metrics.bytes_in = state.bytes_in as f64 / elapsed;
+        state.metrics.bytes_out = state.bytes_out as f64 / elapsed;
+        state.messages_in = 0;
+        state.messages_out = 0;
+        state.bytes_in = 0;
+        state.bytes_out = 0;
+        state.window_start = Instant::now();
+        state.updated = true;
+    }
+
+    /// Jitter is smoothed like RTP interarrival jitter
+    pub fn record_rtt(&self, rtt: Duration) {
+        let rtt = rtt.as_secs_f64() * 1000.0;
+        let mut state = self.state.lock().unwrap();
+        if let Some(previous) = state.metrics.rtt {
+            let jitter = state.metrics.jitter.unwrap_or_default();
+            state.metrics.jitter = Some(jitter + ((rtt - previous).abs() - jitter) / 16.0);
+        }
+        state.metrics.rtt = Some(rtt);
+    }
+
+    pub fn set_clock_offset(&self, offset: i64) {
+        self.state.lock().unwrap().metrics.clock_offset = Some(offset);
+    }
+
+    /// What the time sync of the connection measured last,
+    /// the clock offset is the same estimate the values are timestamped with
+    pub fn record_time_sync(&self, rtt: Option<Duration>, clock: ServerClock) {
+        if let Some(rtt) = rtt {
+            self.record_rtt(rtt);
+        }
+        self.set_clock_offset(clock.offset);
+    }
+
+    /// Round trips measured on an old connection say nothing about the new one
+    pub fn reset_link(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.metrics.rtt = None;
+        state.metrics.jitter = None;
+        state.metrics.clock_offset = None;
+    }
+
+    /// The metrics if they changed since the last call
+    pub fn take_update(&self) -> Option<LinkMetrics> {
+        let mut state = self.state.lock().unwrap();
+        if state.updated {
+            state.updated = false;
+            Some(state.metrics.clone())
+        } else {
+            None
+        }
+    }
+}
+
+/// The size of a value as it goes over the wire
+pub fn encoded_len(value: &rmpv::Value) -> usize {
+    let mut buf = Vec::new();
+    rmpv::encode::write_value(&mut buf, value)
+        .map(|_| buf.len())
+        .unwrap_or_default()
+}
+
+/// Starts the metrics entries under `prefix`, main thread only
+pub fn start_metrics_entries(prefix: &str) -> Result<(), EnokiError> {
+    for (name, entry_type) in METRICS_ENTRIES {
+        start_datalog_entry(
+            &format!("{}/{}", prefix, name),
+            entry_type,
+            Some("Network table link metrics"),
+        )?;
+    }
+    Ok(())
+}
+
+/// Logs the metrics under `prefix`, main thread only
+pub fn log_metrics(prefix: &str, metrics: &LinkMetrics) -> Result<(), EnokiError> {
+    for ((name, _), value) in METRICS_ENTRIES.iter().zip(metrics.values()) {
+        if let Some(value) = value {
+            log_datalog_value(&format!("{}/{}", prefix, name), value)?;
+        }
+    }
+    Ok(())
+}
+
+/// Logs the metrics of every client that measured new ones since the last frame
+pub fn log_client_metrics() {
+    NETWORK_CLIENT_MAP.with(|map| {
+        for client in map.borrow_mut().values_mut() {
+            log_result_consume(client.log_metrics());
+        }
+    });
+}
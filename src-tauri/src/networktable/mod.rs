@@ -1,4 +1,12 @@
 
 #[macro_use]
 pub mod commands;
-pub mod handler;
\ No newline at end of file
+pub mod connection;
+pub mod discovery;
+pub mod handler;
+pub mod history;
+pub mod ipc;
+pub mod metrics;
+pub mod recorder;
+pub mod server;
+pub mod topics;
\ No newline at end of file
@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+use crate::datalog::handler::{
+    datalog_type, end_datalog_entry, log_datalog_value_at, start_datalog_entry,
+};
+use crate::error::{log_result_consume, EnokiError};
+use crate::mushroom_types::MushroomEntry;
+use crate::time::{MonotonicTime, ServerClock};
+use crate::NETWORK_CLIENT_MAP;
+
+/// The nt4 task's half of an [`NtRecorder`], only sends while recording is on
+#[derive(Debug, Clone)]
+pub struct RecordingSender {
+    recording: Arc<AtomicBool>,
+    sender: UnboundedSender<(MushroomEntry, MonotonicTime)>,
+}
+
+impl RecordingSender {
+    /// `clock` moves the server timestamp of the entry to the time base of the datalog
+    pub fn record(&self, entry: &MushroomEntry, clock: &ServerClock) {
+        if self.recording.load(Ordering::Relaxed) {
+            let time = match entry.get_timestamp() {
+                Some(time) => clock.to_monotonic(time),
+                None => MonotonicTime::now(),
+            };
+            self.sender.send((entry.clone(), time)).ok();
+        }
+    }
+}
+
+/// Mirrors the values a network table client receives into the session datalog,
+/// the datalog lives on the main thread so the values are written from [`write_recordings`]
+#[derive(Debug)]
+pub struct NtRecorder {
+    prefix: String,
+    recording: Arc<AtomicBool>,
+    receiver: UnboundedReceiver<(MushroomEntry, MonotonicTime)>,
+    /// Datalog entries this recorder started and their types
+    started: HashMap<String, &'static str>,
+}
+
+impl NtRecorder {
+    pub fn new(prefix: String) -> (Self, RecordingSender) {
+        let recording = Arc::new(AtomicBool::new(false));
+        let (sender, receiver) = unbounded_channel();
+        (
+            Self {
+                prefix,
+                recording: recording.clone(),
+                receiver,
+                started: HashMap::new(),
+            },
+            RecordingSender { recording, sender },
+        )
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.load(Ordering::Relaxed)
+    }
+
+    /// Turning recording off writes what is left and finishes the started entries
+    pub fn set_recording(&mut self, recording: bool, prefix: Option<String>) {
+        if !recording && self.is_recording() {
+            self.recording.store(false, Ordering::Relaxed);
+            self.write();
+            for name in self.started.keys() {
+                log_result_consume(end_datalog_entry(name));
+            }
+            self.started.clear();
+        }
+        if let Some(prefix) = prefix {
+            self.prefix = prefix;
+        }
+        self.recording.store(recording, Ordering::Relaxed);
+    }
+
+    /// Writes everything received since the last call, main thread only
+    pub fn write(&mut self) {
+        while let Ok((entry, time)) = self.receiver.try_recv() {
+            log_result_consume(self.write_entry(entry, time));
+        }
+    }
+
+    fn write_entry(&mut self, entry: MushroomEntry, time: MonotonicTime) -> Result<(), EnokiError> {
+        let value = entry.get_value();
+        let entry_type = datalog_type(&value);
+        let name = format!("{}{}", self.prefix, String::from(entry.get_path()));
+        match self.started.get(&name) {
+            Some(started_type) if *started_type == entry_type => {}
+            started => {
+                //an entry keeps the type it was started with
+                if started.is_some() {
+                    end_datalog_entry(&name)?;
+                }
+                start_datalog_entry(&name, entry_type, None)?;
+                self.started.insert(name.clone(), entry_type);
+            }
+        }
+        log_datalog_value_at(&name, value, time)
+    }
+}
+
+/// Writes the values every recording client received since the last frame
+pub fn write_recordings() {
+    NETWORK_CLIENT_MAP.with(|map| {
+        for client in map.borrow_mut().values_mut() {
+            client.write_recording();
+        }
+    });
+}
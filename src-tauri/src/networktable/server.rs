@@ -0,0 +1,653 @@
+use std::collections::{HashMap, HashSet};
+use std::io::Cursor;
+use std::net::{SocketAddr, TcpListener as StdTcpListener};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Map as JsonMap, Value as JsonValue};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::Handle;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tokio::task::{JoinHandle as TokioJoinHandle, JoinSet};
+use tokio_tungstenite::tungstenite::handshake::server::{
+    Callback, ErrorResponse, Request, Response,
+};
+use tokio_tungstenite::tungstenite::http::HeaderValue;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::error::EnokiError;
+use crate::{check_if_main_thread, THREAD_POOL};
+
+pub const DEFAULT_NT4_PORT: u16 = 5810;
+
+/// The websocket subprotocols the server speaks, preferred first
+const NT4_PROTOCOLS: [&str; 2] = [
+    "v4.1.networktables.first.wpi.edu",
+    "networktables.first.wpi.edu",
+];
+
+/// The publisher id clients use for time synchronization
+const TIME_SYNC_ID: i64 = -1;
+
+/// The NT4 type id values of `topic_type` are sent with,
+/// types the spec doesn't list like structs are sent as raw bytes
+fn nt4_type_id(topic_type: &str) -> u64 {
+    match topic_type {
+        "boolean" => 0,
+        "double" => 1,
+        "int" => 2,
+        "float" => 3,
+        "string" | "json" => 4,
+        "boolean[]" => 16,
+        "double[]" => 17,
+        "int[]" => 18,
+        "float[]" => 19,
+        "string[]" => 20,
+        _ => 5,
+    }
+}
+
+#[derive(Debug)]
+struct ServerTopic {
+    id: i64,
+    name: String,
+    topic_type: String,
+    properties: JsonMap<String, JsonValue>,
+    /// Timestamp, type id and value of the last update
+    last: Option<(u64, u64, rmpv::Value)>,
+    publishers: usize,
+}
+
+impl ServerTopic {
+    /// Retained and persistent topics outlive their last publisher
+    fn is_retained(&self) -> bool {
+        ["retained", "persistent"]
+            .iter()
+            .any(|property| self.properties.get(*property) == Some(&JsonValue::Bool(true)))
+    }
+
+    fn announce_message(&self, pubuid: Option<i64>) -> JsonValue {
+        let mut params = json!({
+            "name": self.name,
+            "id": self.id,
+            "type": self.topic_type,
+            "properties": self.properties,
+        });
+        if let Some(pubuid) = pubuid {
+            params["pubuid"] = json!(pubuid);
+        }
+        json!({ "method": "announce", "params": params })
+    }
+}
+
+#[derive(Debug)]
+struct ServerSubscription {
+    topics: Vec<String>,
+    prefix: bool,
+    topics_only: bool,
+}
+
+impl ServerSubscription {
+    fn matches(&self, name: &str) -> bool {
+        self.topics.iter().any(|topic| {
+            if self.prefix {
+                name.starts_with(topic.as_str())
+            } else {
+                name == topic
+            }
+        })
+    }
+}
+
+#[derive(Debug)]
+struct ServerClient {
+    name: String,
+    sender: UnboundedSender<Message>,
+    subscriptions: HashMap<i64, ServerSubscription>,
+    /// Topic names by pubuid
+    publishers: HashMap<i64, String>,
+    /// Ids of the topics announced to this client
+    announced: HashSet<i64>,
+}
+
+impl ServerClient {
+    fn wants_topic(&self, name: &str) -> bool {
+        self.subscriptions.values().any(|sub| sub.matches(name))
+    }
+
+    fn wants_values(&self, name: &str) -> bool {
+        self.subscriptions
+            .values()
+            .any(|sub| !sub.topics_only && sub.matches(name))
+    }
+
+    fn send_text(&self, messages: Vec<JsonValue>) {
+        self.sender
+            .send(Message::Text(JsonValue::Array(messages).to_string()))
+            .ok();
+    }
+
+    fn send_value(&self, id: i64, timestamp: u64, type_id: u64, value: rmpv::Value) {
+        let mut buf = Vec::new();
+        let message = rmpv::Value::Array(vec![id.into(), timestamp.into(), type_id.into(), value]);
+        if rmpv::encode::write_value(&mut buf, &message).is_ok() {
+            self.sender.send(Message::Binary(buf)).ok();
+        }
+    }
+}
+
+/// The topics and clients of a [`NetworkTableServer`],
+/// every socket only hands its messages to this
+#[derive(Debug)]
+pub struct ServerState {
+    start: Instant,
+    next_topic_id: i64,
+    next_client_id: u64,
+    topics: HashMap<String, ServerTopic>,
+    clients: HashMap<u64, ServerClient>,
+}
+
+impl Default for ServerState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ServerState {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            next_topic_id: 0,
+            next_client_id: 0,
+            topics: HashMap::new(),
+            clients: HashMap::new(),
+        }
+    }
+
+    /// Microseconds since the server started
+    pub fn server_time(&self) -> u64 {
+        self.start.elapsed().as_micros() as u64
+    }
+
+    /// Sorted by name
+    pub fn client_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .clients
+            .values()
+            .map(|client| client.name.clone())
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Sorted by name
+    pub fn topic_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.topics.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Returns the id the client's messages have to be handled under
+    pub fn add_client(&mut self, name: String, sender: UnboundedSender<Message>) -> u64 {
+        let client_id = self.next_client_id;
+        self.next_client_id += 1;
+        self.clients.insert(
+            client_id,
+            ServerClient {
+                name,
+                sender,
+                subscriptions: HashMap::new(),
+                publishers: HashMap::new(),
+                announced: HashSet::new(),
+            },
+        );
+        client_id
+    }
+
+    /// Everything the client published goes away with it
+    pub fn remove_client(&mut self, client_id: u64) {
+        if let Some(client) = self.clients.remove(&client_id) {
+            for name in client.publishers.values() {
+                self.release_topic(name);
+            }
+        }
+    }
+
+    /// A text frame holds a json array of control messages
+    pub fn handle_text(&mut self, client_id: u64, text: &str) {
+        let messages = match serde_json::from_str(text) {
+            Ok(JsonValue::Array(messages)) => messages,
+            _ => {
+                tracing::warn!("Malformed NT4 text frame from client {}", client_id);
+                return;
+            }
+        };
+        for message in messages {
+            let params = &message["params"];
+            match message["method"].as_str() {
+                Some("publish") => self.publish(client_id, params),
+                Some("unpublish") => self.unpublish(client_id, params),
+                Some("setproperties") => self.set_properties(params),
+                Some("subscribe") => self.subscribe(client_id, params),
+                Some("unsubscribe") => self.unsubscribe(client_id, params),
+                method => tracing::warn!("Unknown NT4 method {:?}", method),
+            }
+        }
+    }
+
+    /// A binary frame holds one or more msgpack arrays of
+    /// publisher id, timestamp, type id and value
+    pub fn handle_binary(&mut self, client_id: u64, data: &[u8]) {
+        let mut cursor = Cursor::new(data);
+        while (cursor.position() as usize) < data.len() {
+            match rmpv::decode::read_value(&mut cursor) {
+                Ok(rmpv::Value::Array(fields)) if fields.len() == 4 => {
+                    self.value(client_id, fields)
+                }
+                Ok(_) => tracing::warn!("Malformed NT4 value from client {}", client_id),
+                Err(err) => {
+                    tracing::warn!("Undecodable NT4 frame from client {}: {}", client_id, err);
+                    return;
+                }
+            }
+        }
+    }
+
+    fn publish(&mut self, client_id: u64, params: &JsonValue) {
+        let (name, pubuid, topic_type) = match (
+            params["name"].as_str(),
+            params["pubuid"].as_i64(),
+            params["type"].as_str(),
+        ) {
+            (Some(name), Some(pubuid), Some(topic_type)) => (name, pubuid, topic_type),
+            _ => {
+                tracing::warn!("Malformed NT4 publish from client {}", client_id);
+                return;
+            }
+        };
+        //a reused pubuid replaces the publisher it belonged to
+        let previous = match self.clients.get_mut(&client_id) {
+            Some(client) => client.publishers.remove(&pubuid),
+            None => return,
+        };
+        if let Some(previous) = previous {
+            self.release_topic(&previous);
+        }
+        let mut retyped = false;
+        if let Some(topic) = self.topics.get_mut(name) {
+            if topic.topic_type != topic_type {
+                if topic.publishers > 0 {
+                    tracing::warn!(
+                        "Client {} published {} as {} but it is published as {}",
+                        client_id,
+                        name,
+                        topic_type,
+                        topic.topic_type
+                    );
+                    return;
+                }
+                //a retained topic nobody publishes takes the new type, its value doesn't fit it
+                topic.topic_type = String::from(topic_type);
+                topic.last = None;
+                retyped = true;
+            }
+        }
+        if let Some(client) = self.clients.get_mut(&client_id) {
+            client.publishers.insert(pubuid, String::from(name));
+        }
+        if !self.topics.contains_key(name) {
+            self.topics.insert(
+                String::from(name),
+                ServerTopic {
+                    id: self.next_topic_id,
+                    name: String::from(name),
+                    topic_type: String::from(topic_type),
+                    properties: params["properties"]
+                        .as_object()
+                        .cloned()
+                        .unwrap_or_default(),
+                    last: None,
+                    publishers: 0,
+                },
+            );
+            self.next_topic_id += 1;
+        }
+        let topic = self.topics.get_mut(name).unwrap();
+        topic.publishers += 1;
+        //the publisher always hears about its own topic
+        for (id, client) in self.clients.iter_mut() {
+            if *id == client_id {
+                client.send_text(vec![topic.announce_message(Some(pubuid))]);
+                client.announced.insert(topic.id);
+            } else if client.wants_topic(name) && (client.announced.insert(topic.id) || retyped) {
+                client.send_text(vec![topic.announce_message(None)]);
+            }
+        }
+    }
+
+    fn unpublish(&mut self, client_id: u64, params: &JsonValue) {
+        let name = params["pubuid"].as_i64().and_then(|pubuid| {
+            self.clients
+                .get_mut(&client_id)
+                .and_then(|client| client.publishers.remove(&pubuid))
+        });
+        if let Some(name) = name {
+            self.release_topic(&name);
+        }
+    }
+
+    fn release_topic(&mut self, name: &str) {
+        let topic = match self.topics.get_mut(name) {
+            Some(topic) => topic,
+            None => return,
+        };
+        topic.publishers = topic.publishers.saturating_sub(1);
+        self.drop_unused_topic(name);
+    }
+
+    /// Removes the topic if nobody publishes it and it isn't retained
+    fn drop_unused_topic(&mut self, name: &str) {
+        match self.topics.get(name) {
+            Some(topic) if topic.publishers == 0 && !topic.is_retained() => {}
+            _ => return,
+        }
+        let topic = self.topics.remove(name).unwrap();
+        for client in self.clients.values_mut() {
+            if client.announced.remove(&topic.id) {
+                client.send_text(vec![json!({
+                    "method": "unannounce",
+                    "params": { "name": topic.name, "id": topic.id },
+                })]);
+            }
+        }
+    }
+
+    fn set_properties(&mut self, params: &JsonValue) {
+        let (topic, update) = match (
+            params["name"]
+                .as_str()
+                .and_then(|name| self.topics.get_mut(name)),
+            params["update"].as_object(),
+        ) {
+            (Some(topic), Some(update)) => (topic, update),
+            _ => return,
+        };
+        for (key, value) in update {
+            //null deletes the property
+            if value.is_null() {
+                topic.properties.remove(key);
+            } else {
+                topic.properties.insert(key.clone(), value.clone());
+            }
+        }
+        for client in self.clients.values() {
+            if client.announced.contains(&topic.id) {
+                client.send_text(vec![json!({
+                    "method": "properties",
+                    "params": { "name": topic.name, "ack": true, "update": update },
+                })]);
+            }
+        }
+        //a topic nobody publishes anymore only stayed for being retained
+        let name = topic.name.clone();
+        self.drop_unused_topic(&name);
+    }
+
+    fn subscribe(&mut self, client_id: u64, params: &JsonValue) {
+        let (subuid, topics) = match (params["subuid"].as_i64(), params["topics"].as_array()) {
+            (Some(subuid), Some(topics)) => (subuid, topics),
+            _ => {
+                tracing::warn!("Malformed NT4 subscribe from client {}", client_id);
+                return;
+            }
+        };
+        let options = &params["options"];
+        let subscription = ServerSubscription {
+            topics: topics
+                .iter()
+                .filter_map(|topic| topic.as_str().map(String::from))
+                .collect(),
+            prefix: options["prefix"].as_bool().unwrap_or(false),
+            topics_only: options["topicsonly"].as_bool().unwrap_or(false),
+        };
+        let client = match self.clients.get_mut(&client_id) {
+            Some(client) => client,
+            None => return,
+        };
+        for topic in self.topics.values() {
+            if !subscription.matches(&topic.name) {
+                continue;
+            }
+            if client.announced.insert(topic.id) {
+                client.send_text(vec![topic.announce_message(None)]);
+            }
+            if let (false, Some((timestamp, type_id, value))) =
+                (subscription.topics_only, &topic.last)
+            {
+                client.send_value(topic.id, *timestamp, *type_id, value.clone());
+            }
+        }
+        client.subscriptions.insert(subuid, subscription);
+    }
+
+    fn unsubscribe(&mut self, client_id: u64, params: &JsonValue) {
+        if let (Some(client), Some(subuid)) =
+            (self.clients.get_mut(&client_id), params["subuid"].as_i64())
+        {
+            client.subscriptions.remove(&subuid);
+        }
+    }
+
+    fn value(&mut self, client_id: u64, mut fields: Vec<rmpv::Value>) {
+        let value = fields.pop().unwrap();
+        let (id, timestamp, type_id) =
+            match (fields[0].as_i64(), fields[1].as_u64(), fields[2].as_u64()) {
+                (Some(id), Some(timestamp), Some(type_id)) => (id, timestamp, type_id),
+                _ => return,
+            };
+        let server_time = self.server_time();
+        let client = match self.clients.get(&client_id) {
+            Some(client) => client,
+            None => return,
+        };
+        if id == TIME_SYNC_ID {
+            //the client gets its own time back to work out the offset
+            client.send_value(TIME_SYNC_ID, server_time, type_id, value);
+            return;
+        }
+        let topic = match client
+            .publishers
+            .get(&id)
+            .and_then(|name| self.topics.get_mut(name))
+        {
+            Some(topic) => topic,
+            None => return,
+        };
+        if type_id != nt4_type_id(&topic.topic_type) {
+            tracing::warn!(
+                "Client {} sent a value of type {} to {} of type {}",
+                client_id,
+                type_id,
+                topic.name,
+                topic.topic_type
+            );
+            return;
+        }
+        //zero means the client hasn't synchronized its clock yet
+        let timestamp = if timestamp == 0 {
+            server_time
+        } else {
+            timestamp
+        };
+        topic.last = Some((timestamp, type_id, value.clone()));
+        for client in self.clients.values() {
+            if client.wants_values(&topic.name) {
+                client.send_value(topic.id, timestamp, type_id, value.clone());
+            }
+        }
+    }
+}
+
+/// An NT4 server hosted by the backend,
+/// our own clients and simulation programs can connect to it like to a robot
+#[derive(Debug)]
+pub struct NetworkTableServer {
+    port: u16,
+    state: Arc<Mutex<ServerState>>,
+    thread: TokioJoinHandle<()>,
+}
+
+impl NetworkTableServer {
+    /// Listens on `port`, any free port for 0, and serves its clients on `runtime`
+    pub fn bind(port: u16, runtime: &Handle) -> Result<Self, EnokiError> {
+        //bound here so a taken port is reported to the caller
+        let listener = StdTcpListener::bind(("0.0.0.0", port))
+            .and_then(|listener| listener.set_nonblocking(true).map(|_| listener))
+            .and_then(|listener| listener.local_addr().map(|address| (listener, address)));
+        let (listener, address) =
+            listener.map_err(|err| EnokiError::NTServer(format!("port {}: {}", port, err)))?;
+        let state = Arc::new(Mutex::new(ServerState::new()));
+        let thread = runtime.spawn(serve_nt4(listener, state.clone()));
+        tracing::info!("Network table server listening on port {}", address.port());
+
+        Ok(Self {
+            port: address.port(),
+            state,
+            thread,
+        })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn client_names(&self) -> Vec<String> {
+        self.state.lock().unwrap().client_names()
+    }
+
+    pub fn topic_names(&self) -> Vec<String> {
+        self.state.lock().unwrap().topic_names()
+    }
+
+    /// Closes every connection with the listener
+    pub fn stop(&self) {
+        self.thread.abort();
+    }
+}
+
+pub fn start_nt4_server(port: u16) -> Result<NetworkTableServer, EnokiError> {
+    check_if_main_thread()?;
+
+    THREAD_POOL.with(|thread_pool| {
+        NetworkTableServer::bind(port, thread_pool.borrow().as_ref().unwrap().handle())
+    })
+}
+
+async fn serve_nt4(listener: StdTcpListener, state: Arc<Mutex<ServerState>>) {
+    let listener = match TcpListener::from_std(listener) {
+        Ok(listener) => listener,
+        Err(err) => {
+            tracing::error!("Network table server failed to listen because {}", err);
+            return;
+        }
+    };
+    //dropping the set when the server is aborted closes every connection
+    let mut connections = JoinSet::new();
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, address)) => {
+                    connections.spawn(serve_nt4_client(state.clone(), stream, address));
+                }
+                Err(err) => tracing::error!("Network table server failed to accept because {}", err),
+            },
+            Some(_) = connections.join_next() => {}
+        }
+    }
+}
+
+/// Names the client after its path and picks the NT4 protocol it asked for
+struct Nt4Handshake<'a> {
+    address: SocketAddr,
+    name: &'a mut String,
+}
+
+impl Callback for Nt4Handshake<'_> {
+    fn on_request(
+        self,
+        request: &Request,
+        mut response: Response,
+    ) -> Result<Response, ErrorResponse> {
+        //clients connect to /nt/<name>
+        if let Some(client_name) = request.uri().path().strip_prefix("/nt/") {
+            *self.name = format!("{}@{}", client_name, self.address);
+        }
+        let requested = request
+            .headers()
+            .get("Sec-WebSocket-Protocol")
+            .and_then(|protocols| protocols.to_str().ok())
+            .unwrap_or_default();
+        match NT4_PROTOCOLS.iter().find(|protocol| {
+            requested
+                .split(',')
+                .any(|requested| requested.trim() == **protocol)
+        }) {
+            Some(protocol) => {
+                response
+                    .headers_mut()
+                    .insert("Sec-WebSocket-Protocol", HeaderValue::from_static(protocol));
+            }
+            None => tracing::warn!("{} did not ask for an NT4 protocol", self.address),
+        }
+        Ok(response)
+    }
+}
+
+async fn serve_nt4_client(state: Arc<Mutex<ServerState>>, stream: TcpStream, address: SocketAddr) {
+    let mut name = address.to_string();
+    let handshake = tokio_tungstenite::accept_hdr_async(
+        stream,
+        Nt4Handshake {
+            address,
+            name: &mut name,
+        },
+    )
+    .await;
+    let socket = match handshake {
+        Ok(socket) => socket,
+        Err(err) => {
+            tracing::warn!(
+                "Network table server handshake with {} failed because {}",
+                address,
+                err
+            );
+            return;
+        }
+    };
+
+    let (mut write, mut read) = socket.split();
+    let (sender, mut receiver) = unbounded_channel::<Message>();
+    let client_id = state.lock().unwrap().add_client(name.clone(), sender);
+    tracing::info!("{} connected to the network table server", name);
+    let writer = tokio::spawn(async move {
+        while let Some(message) = receiver.recv().await {
+            if write.send(message).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    while let Some(Ok(message)) = read.next().await {
+        match message {
+            Message::Text(text) => state.lock().unwrap().handle_text(client_id, &text),
+            Message::Binary(data) => state.lock().unwrap().handle_binary(client_id, &data),
+            Message::Close(_) => break,
+            _ => {}
+        }
+    }
+
+    state.lock().unwrap().remove_client(client_id);
+    writer.abort();
+    tracing::info!("{} disconnected from the network table server", name);
+}
@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use network_tables::v4::{PublishProperties, Topic, Type};
+use tauri::{AppHandle, Manager};
+
+use super::handler::NetworkTableClientId;
+
+/// Emitted when the server announces or unannounces a topic,
+/// the payload is a [`TopicUpdate`]
+pub const NT_TOPIC_EVENT: &str = "nt_topic";
+
+/// The NT4 type string, e.g. `double[]` or `struct:Pose2d`
+pub fn type_string(topic_type: &Type) -> String {
+    match serde_json::to_value(topic_type) {
+        Ok(serde_json::Value::String(type_str)) => type_str,
+        _ => format!("{:?}", topic_type),
+    }
+}
+
+/// The NT4 properties the dashboard can set on the topics it publishes
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct TopicProperties {
+    /// Saved by the server and restored when it restarts
+    pub persistent: Option<bool>,
+    /// Kept by the server after the last publisher is gone
+    pub retained: Option<bool>,
+    /// Whether the server keeps the last value to send to new subscribers
+    pub cached: Option<bool>,
+}
+
+impl TopicProperties {
+    /// The NT4 `setproperties` update that makes these the properties of a topic,
+    /// the ones that aren't set are null so the server deletes them
+    pub fn update(&self) -> HashMap<String, serde_json::Value> {
+        HashMap::from([
+            (
+                String::from("persistent"),
+                serde_json::json!(self.persistent),
+            ),
+            (String::from("retained"), serde_json::json!(self.retained)),
+            (String::from("cached"), serde_json::json!(self.cached)),
+        ])
+    }
+}
+
+impl From<TopicProperties> for PublishProperties {
+    fn from(properties: TopicProperties) -> Self {
+        Self {
+            persistent: properties.persistent,
+            retained: properties.retained,
+            rest: properties.cached.map(|cached| {
+                HashMap::from([(String::from("cached"), serde_json::Value::Bool(cached))])
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct TopicInfo {
+    name: String,
+    id: i32,
+    #[serde(rename = "type")]
+    topic_type: String,
+    properties: serde_json::Value,
+    publisher: Option<i32>,
+}
+
+impl TopicInfo {
+    /// For topics that don't come from a server
+    pub fn new(name: String, id: i32, topic_type: String) -> Self {
+        Self {
+            name,
+            id,
+            topic_type,
+            properties: serde_json::Value::Object(Default::default()),
+            publisher: None,
+        }
+    }
+}
+
+impl From<&Topic> for TopicInfo {
+    fn from(topic: &Topic) -> Self {
+        Self {
+            name: topic.name.clone(),
+            id: topic.id,
+            topic_type: type_string(&topic.r#type),
+            properties: serde_json::to_value(&topic.properties).unwrap_or_default(),
+            publisher: topic.pubuid,
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(tag = "change")]
+pub enum TopicChange {
+    Announced { topic: TopicInfo },
+    Unannounced { name: String },
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct TopicUpdate {
+    client_id: NetworkTableClientId,
+    #[serde(flatten)]
+    change: TopicChange,
+}
+
+/// Every topic the server announced to a client, kept up to date by the nt4 task
+#[derive(Debug, Clone)]
+pub struct TopicCatalog {
+    id: NetworkTableClientId,
+    app_handle: AppHandle,
+    topics: Arc<Mutex<HashMap<String, TopicInfo>>>,
+}
+
+impl TopicCatalog {
+    pub fn new(id: NetworkTableClientId, app_handle: AppHandle) -> Self {
+        Self {
+            id,
+            app_handle,
+            topics: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn announce(&self, topic: &Topic) {
+        self.announce_info(TopicInfo::from(topic));
+    }
+
+    pub fn announce_info(&self, info: TopicInfo) {
+        self.topics
+            .lock()
+            .unwrap()
+            .insert(info.name.clone(), info.clone());
+        self.emit(TopicChange::Announced { topic: info });
+    }
+
+    pub fn unannounce(&self, name: &str) {
+        if self.topics.lock().unwrap().remove(name).is_some() {
+            self.emit(TopicChange::Unannounced {
+                name: String::from(name),
+            });
+        }
+    }
+
+    /// The server announces everything again after a reconnect
+    pub fn clear(&self) {
+        let names: Vec<String> = self.topics.lock().unwrap().keys().cloned().collect();
+        for name in names {
+            self.unannounce(&name);
+        }
+    }
+
+    /// The NT4 type string of the topic if it was announced
+    pub fn topic_type(&self, name: &str) -> Option<String> {
+        self.topics
+            .lock()
+            .unwrap()
+            .get(name)
+            .map(|topic| topic.topic_type.clone())
+    }
+
+    /// Sorted by name, only topics starting with `prefix` if one is given
+    pub fn list(&self, prefix: Option<&str>) -> Vec<TopicInfo> {
+        let mut topics: Vec<TopicInfo> = self
+            .topics
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|topic| prefix.map_or(true, |prefix| topic.name.starts_with(prefix)))
+            .cloned()
+            .collect();
+        topics.sort_by(|a, b| a.name.cmp(&b.name));
+        topics
+    }
+
+    fn emit(&self, change: TopicChange) {
+        self.app_handle
+            .emit_all(
+                NT_TOPIC_EVENT,
+                TopicUpdate {
+                    client_id: self.id.clone(),
+                    change,
+                },
+            )
+            .unwrap_or_else(|err| {
+                tracing::error!(
+                    "Failed to emit topic update for {} because {}",
+                    self.id,
+                    err
+                );
+            });
+    }
+}
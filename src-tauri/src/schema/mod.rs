@@ -0,0 +1,256 @@
+pub mod protobuf;
+pub mod structs;
+
+use crate::mushroom_types::{MushroomEntry, MushroomValue};
+use crate::time::ServerTime;
+
+use self::protobuf::{ProtobufRegistry, PROTOBUF_TYPE_PREFIX};
+use self::structs::{StructRegistry, STRUCT_TYPE_PREFIX};
+
+/// Robot code publishes the schemas of its struct topics under this prefix,
+/// the rest of the name is the type they describe, e.g. `/.schema/struct:Pose2d`
+pub const SCHEMA_PREFIX: &str = "/.schema/";
+
+/// A value decoded with a WPILib struct or protobuf schema, it keeps the bytes
+/// it was decoded from so it is sent on and logged exactly as it was received
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct StructValue {
+    /// The type of the topic, e.g. `struct:Pose2d` or `proto:wpi.proto.ProtobufPose2d`
+    pub type_name: String,
+    /// An object of the named fields in schema order, an array of them for struct arrays
+    pub fields: serde_json::Value,
+    pub raw: Vec<u8>,
+}
+
+/// The struct and protobuf schemas a client or replay has seen so far
+#[derive(Debug, Clone, Default)]
+pub struct SchemaRegistry {
+    pub structs: StructRegistry,
+    pub protobufs: ProtobufRegistry,
+}
+
+impl SchemaRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `type_name` is the name of the schema topic without [`SCHEMA_PREFIX`]
+    pub fn add(&mut self, type_name: &str, schema: &[u8]) -> Result<(), String> {
+        if type_name.starts_with(STRUCT_TYPE_PREFIX) {
+            self.structs
+                .add(type_name, &String::from_utf8_lossy(schema))
+        } else if type_name.starts_with(PROTOBUF_TYPE_PREFIX) {
+            self.protobufs.add_file(schema)
+        } else {
+            Err(format!("unknown schema type {}", type_name))
+        }
+    }
+
+    /// Decodes a value of a `struct:` or `proto:` typed topic
+    pub fn decode(&self, type_name: &str, raw: &[u8]) -> Result<FieldValue, String> {
+        if type_name.starts_with(PROTOBUF_TYPE_PREFIX) {
+            self.protobufs.decode_fields(type_name, raw)
+        } else {
+            self.structs.decode_fields(type_name, raw)
+        }
+    }
+
+    /// Whether values of the type can have a schema
+    pub fn is_schema_type(type_name: &str) -> bool {
+        type_name.starts_with(STRUCT_TYPE_PREFIX) || type_name.starts_with(PROTOBUF_TYPE_PREFIX)
+    }
+}
+
+/// Registers the schemas published under [`SCHEMA_PREFIX`] and turns values of struct
+/// and protobuf topics into a [`MushroomValue::Struct`] followed by an entry per field,
+/// everything else comes back as it was
+pub fn expand_entry(
+    registry: &mut SchemaRegistry,
+    entry: MushroomEntry,
+    topic_type: Option<&str>,
+) -> Vec<MushroomEntry> {
+    let path = String::from(entry.get_path());
+    let raw = match entry.get_value() {
+        MushroomValue::ByteArray(raw) | MushroomValue::Protobuf(raw) => raw,
+        MushroomValue::String(schema) if path.contains(SCHEMA_PREFIX) => schema.into_bytes(),
+        _ => return vec![entry],
+    };
+
+    //datalogs keep the schemas under the prefix of the logged table, e.g. `NT:/.schema/`
+    if let Some((_, type_name)) = path.split_once(SCHEMA_PREFIX) {
+        if SchemaRegistry::is_schema_type(type_name) {
+            registry.add(type_name, &raw).unwrap_or_else(|err| {
+                tracing::warn!("Failed to parse schema {}: {}", type_name, err);
+            });
+        }
+        return vec![entry];
+    }
+
+    let type_name = match topic_type {
+        Some(type_name) if SchemaRegistry::is_schema_type(type_name) => type_name,
+        _ => return vec![entry],
+    };
+    match registry.decode(type_name, &raw) {
+        Ok(fields) => {
+            let timestamp = entry.get_timestamp();
+            let mut entries = vec![MushroomEntry::new(
+                MushroomValue::Struct(StructValue {
+                    type_name: String::from(type_name),
+                    fields: fields.to_json(),
+                    raw,
+                }),
+                entry.get_path(),
+                timestamp,
+            )];
+            entries.extend(field_entries(&path, &fields, timestamp));
+            entries
+        }
+        Err(err) => {
+            //the schema may not have arrived yet, the raw value is still useful
+            tracing::debug!("Failed to decode {}: {}", path, err);
+            vec![entry]
+        }
+    }
+}
+
+/// A value decoded with a schema, typed by the declarations of its fields instead of
+/// by what the decoded values look like
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldValue {
+    /// Scalars and arrays of scalars
+    Value(MushroomValue),
+    /// The named fields of a struct or message in schema order
+    Fields(Vec<(String, FieldValue)>),
+    /// Arrays of structs, messages or byte arrays
+    Array(Vec<FieldValue>),
+    /// Protobuf messages that were never set
+    Null,
+}
+
+impl FieldValue {
+    /// The elements of an array field, scalars become one array of their declared type
+    pub fn array(scalar: Option<ScalarType>, elements: Vec<FieldValue>) -> Self {
+        let values = elements
+            .iter()
+            .map(|element| match element {
+                FieldValue::Value(value) => Some(value.clone()),
+                _ => None,
+            })
+            .collect::<Option<Vec<MushroomValue>>>();
+        let array = scalar
+            .zip(values)
+            .and_then(|(scalar, values)| scalar.array(values));
+        match array {
+            Some(array) => FieldValue::Value(array),
+            None => FieldValue::Array(elements),
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            FieldValue::Value(value) => json_value(value),
+            FieldValue::Fields(fields) => serde_json::Value::Object(
+                fields
+                    .iter()
+                    .map(|(name, field)| (name.clone(), field.to_json()))
+                    .collect(),
+            ),
+            FieldValue::Array(elements) => {
+                serde_json::Value::Array(elements.iter().map(FieldValue::to_json).collect())
+            }
+            FieldValue::Null => serde_json::Value::Null,
+        }
+    }
+}
+
+/// The value types a schema can declare a field as, enums are strings of their names
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScalarType {
+    Boolean,
+    Int,
+    Float,
+    Double,
+    String,
+    Bytes,
+}
+
+impl ScalarType {
+    /// `None` for byte arrays, there are no arrays of them
+    fn array(self, values: Vec<MushroomValue>) -> Option<MushroomValue> {
+        Some(match self {
+            ScalarType::Boolean => {
+                MushroomValue::BooleanArray(values.into_iter().map(bool::from).collect())
+            }
+            ScalarType::Int => MushroomValue::IntArray(values.into_iter().map(i64::from).collect()),
+            ScalarType::Float => {
+                MushroomValue::FloatArray(values.into_iter().map(f64::from).collect())
+            }
+            ScalarType::Double => {
+                MushroomValue::DoubleArray(values.into_iter().map(f64::from).collect())
+            }
+            ScalarType::String => {
+                MushroomValue::StringArray(values.into_iter().map(String::from).collect())
+            }
+            ScalarType::Bytes => return None,
+        })
+    }
+}
+
+/// Every field of `fields` as its own entry under `path`, e.g. `pose/translation/x`,
+/// elements of struct arrays are addressed by index
+pub fn field_entries(
+    path: &str,
+    fields: &FieldValue,
+    timestamp: Option<ServerTime>,
+) -> Vec<MushroomEntry> {
+    let mut entries = Vec::new();
+    add_field_entries(&mut entries, path, fields, timestamp);
+    entries
+}
+
+fn add_field_entries(
+    entries: &mut Vec<MushroomEntry>,
+    path: &str,
+    value: &FieldValue,
+    timestamp: Option<ServerTime>,
+) {
+    match value {
+        FieldValue::Value(value) => {
+            entries.push(MushroomEntry::new(value.clone(), path.into(), timestamp))
+        }
+        FieldValue::Fields(fields) => {
+            for (name, field) in fields {
+                add_field_entries(entries, &format!("{}/{}", path, name), field, timestamp);
+            }
+        }
+        FieldValue::Array(elements) => {
+            for (index, element) in elements.iter().enumerate() {
+                add_field_entries(entries, &format!("{}/{}", path, index), element, timestamp);
+            }
+        }
+        FieldValue::Null => {}
+    }
+}
+
+/// How a field shows up in the fields of a [`StructValue`]
+fn json_value(value: &MushroomValue) -> serde_json::Value {
+    match value {
+        MushroomValue::Boolean(value) => (*value).into(),
+        MushroomValue::Int(value) => (*value).into(),
+        MushroomValue::Float(value) | MushroomValue::Double(value) => float_value(*value),
+        MushroomValue::String(value) => value.as_str().into(),
+        MushroomValue::ByteArray(value) => value.as_slice().into(),
+        MushroomValue::BooleanArray(values) => values.as_slice().into(),
+        MushroomValue::IntArray(values) => values.as_slice().into(),
+        MushroomValue::FloatArray(values) | MushroomValue::DoubleArray(values) => {
+            serde_json::Value::Array(values.iter().copied().map(float_value).collect())
+        }
+        MushroomValue::StringArray(values) => values.as_slice().into(),
+        MushroomValue::Protobuf(_) | MushroomValue::Struct(_) => serde_json::Value::Null,
+    }
+}
+
+/// NaN and infinities have no json number
+fn float_value(value: f64) -> serde_json::Value {
+    serde_json::Number::from_f64(value).map_or(serde_json::Value::Null, serde_json::Value::Number)
+}
@@ -0,0 +1,453 @@
+use std::collections::HashMap;
+
+use super::{FieldValue, ScalarType};
+use crate::mushroom_types::MushroomValue;
+
+/// Type prefix of NT4 topics holding protobuf messages, e.g. `proto:wpi.proto.ProtobufPose2d`,
+/// their schema topics are named after the `.proto` file they come from
+pub const PROTOBUF_TYPE_PREFIX: &str = "proto:";
+
+/// Nested messages deeper than this are assumed to be malformed
+const MAX_MESSAGE_DEPTH: usize = 64;
+
+/// A field of the protobuf wire format before it is interpreted with a schema
+#[derive(Debug, Clone, Copy)]
+enum WireValue<'a> {
+    Varint(u64),
+    Fixed64(u64),
+    Bytes(&'a [u8]),
+    Fixed32(u32),
+}
+
+struct WireReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> WireReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, position: 0 }
+    }
+
+    fn is_done(&self) -> bool {
+        self.position >= self.bytes.len()
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self
+            .position
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or_else(|| String::from("message ends early"))?;
+        let bytes = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn varint(&mut self) -> Result<u64, String> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.take(1)?[0];
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(String::from("varint is too long"))
+    }
+
+    fn fixed64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn fixed32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    /// The number of the next field and its value
+    fn field(&mut self) -> Result<(u32, WireValue<'a>), String> {
+        let key = self.varint()?;
+        let number = (key >> 3) as u32;
+        let value = match key & 0x7 {
+            0 => WireValue::Varint(self.varint()?),
+            1 => WireValue::Fixed64(self.fixed64()?),
+            2 => {
+                let len = self.varint()? as usize;
+                WireValue::Bytes(self.take(len)?)
+            }
+            5 => WireValue::Fixed32(self.fixed32()?),
+            wire_type => return Err(format!("unsupported wire type {}", wire_type)),
+        };
+        Ok((number, value))
+    }
+}
+
+fn wire_fields(bytes: &[u8]) -> Result<Vec<(u32, WireValue<'_>)>, String> {
+    let mut reader = WireReader::new(bytes);
+    let mut fields = Vec::new();
+    while !reader.is_done() {
+        fields.push(reader.field()?);
+    }
+    Ok(fields)
+}
+
+fn wire_string(value: WireValue) -> Result<String, String> {
+    match value {
+        WireValue::Bytes(bytes) => Ok(String::from_utf8_lossy(bytes).into_owned()),
+        other => Err(format!("expected a string, got {:?}", other)),
+    }
+}
+
+fn wire_varint(value: WireValue) -> Result<u64, String> {
+    match value {
+        WireValue::Varint(value) => Ok(value),
+        other => Err(format!("expected a varint, got {:?}", other)),
+    }
+}
+
+/// The field types of `FieldDescriptorProto.Type`
+mod field_type {
+    pub const DOUBLE: u64 = 1;
+    pub const FLOAT: u64 = 2;
+    pub const INT64: u64 = 3;
+    pub const UINT64: u64 = 4;
+    pub const INT32: u64 = 5;
+    pub const FIXED64: u64 = 6;
+    pub const FIXED32: u64 = 7;
+    pub const BOOL: u64 = 8;
+    pub const STRING: u64 = 9;
+    pub const GROUP: u64 = 10;
+    pub const MESSAGE: u64 = 11;
+    pub const BYTES: u64 = 12;
+    pub const UINT32: u64 = 13;
+    pub const ENUM: u64 = 14;
+    pub const SFIXED32: u64 = 15;
+    pub const SFIXED64: u64 = 16;
+    pub const SINT32: u64 = 17;
+    pub const SINT64: u64 = 18;
+}
+
+const LABEL_REPEATED: u64 = 3;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldDescriptor {
+    pub name: String,
+    pub number: u32,
+    pub repeated: bool,
+    pub field_type: u64,
+    /// Fully qualified without the leading dot, only for messages and enums
+    pub type_name: Option<String>,
+}
+
+impl FieldDescriptor {
+    fn parse(bytes: &[u8]) -> Result<Self, String> {
+        let mut field = Self {
+            name: String::new(),
+            number: 0,
+            repeated: false,
+            field_type: 0,
+            type_name: None,
+        };
+        for (number, value) in wire_fields(bytes)? {
+            match number {
+                1 => field.name = wire_string(value)?,
+                3 => field.number = wire_varint(value)? as u32,
+                4 => field.repeated = wire_varint(value)? == LABEL_REPEATED,
+                5 => field.field_type = wire_varint(value)?,
+                6 => {
+                    let type_name = wire_string(value)?;
+                    field.type_name = Some(String::from(type_name.trim_start_matches('.')));
+                }
+                _ => {}
+            }
+        }
+        Ok(field)
+    }
+
+    /// Scalars of repeated fields may be packed into one length delimited field
+    fn packable(&self) -> bool {
+        !matches!(
+            self.field_type,
+            field_type::STRING | field_type::GROUP | field_type::MESSAGE | field_type::BYTES
+        )
+    }
+
+    /// `None` for messages
+    fn scalar_type(&self) -> Option<ScalarType> {
+        match self.field_type {
+            field_type::DOUBLE => Some(ScalarType::Double),
+            field_type::FLOAT => Some(ScalarType::Float),
+            field_type::BOOL => Some(ScalarType::Boolean),
+            field_type::STRING | field_type::ENUM => Some(ScalarType::String),
+            field_type::BYTES => Some(ScalarType::Bytes),
+            field_type::GROUP | field_type::MESSAGE => None,
+            _ => Some(ScalarType::Int),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageDescriptor {
+    pub name: String,
+    pub fields: Vec<FieldDescriptor>,
+}
+
+/// Every message and enum of the `FileDescriptorProto`s published under `/.schema/proto:*`,
+/// keyed by their fully qualified names
+#[derive(Debug, Clone, Default)]
+pub struct ProtobufRegistry {
+    messages: HashMap<String, MessageDescriptor>,
+    enums: HashMap<String, HashMap<i64, String>>,
+}
+
+impl ProtobufRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the messages and enums of a serialized `FileDescriptorProto`,
+    /// files can be added in any order as long as all of them are there before decoding
+    pub fn add_file(&mut self, descriptor: &[u8]) -> Result<(), String> {
+        let mut package = String::new();
+        let mut messages = Vec::new();
+        let mut enums = Vec::new();
+        for (number, value) in wire_fields(descriptor)? {
+            match (number, value) {
+                (2, value) => package = wire_string(value)?,
+                (4, WireValue::Bytes(message)) => messages.push(message),
+                (5, WireValue::Bytes(enum_type)) => enums.push(enum_type),
+                _ => {}
+            }
+        }
+        for message in messages {
+            self.add_message(&package, message)?;
+        }
+        for enum_type in enums {
+            self.add_enum(&package, enum_type)?;
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&MessageDescriptor> {
+        self.messages.get(name)
+    }
+
+    /// Decodes a value of a `proto:package.Message` topic
+    pub fn decode(&self, type_name: &str, raw: &[u8]) -> Result<serde_json::Value, String> {
+        self.decode_fields(type_name, raw)
+            .map(|fields| fields.to_json())
+    }
+
+    /// Like [`ProtobufRegistry::decode`] but keeps the declared type of every field
+    pub fn decode_fields(&self, type_name: &str, raw: &[u8]) -> Result<FieldValue, String> {
+        let name = type_name
+            .strip_prefix(PROTOBUF_TYPE_PREFIX)
+            .ok_or_else(|| format!("{} is not a protobuf type", type_name))?;
+        self.decode_message(name, raw, 0)
+    }
+
+    fn add_message(&mut self, scope: &str, descriptor: &[u8]) -> Result<(), String> {
+        let mut name = String::new();
+        let mut fields = Vec::new();
+        let mut nested = Vec::new();
+        let mut enums = Vec::new();
+        for (number, value) in wire_fields(descriptor)? {
+            match (number, value) {
+                (1, value) => name = wire_string(value)?,
+                (2, WireValue::Bytes(field)) => fields.push(FieldDescriptor::parse(field)?),
+                (3, WireValue::Bytes(message)) => nested.push(message),
+                (4, WireValue::Bytes(enum_type)) => enums.push(enum_type),
+                _ => {}
+            }
+        }
+        let full_name = qualified_name(scope, &name);
+        for message in nested {
+            self.add_message(&full_name, message)?;
+        }
+        for enum_type in enums {
+            self.add_enum(&full_name, enum_type)?;
+        }
+        self.messages.insert(
+            full_name.clone(),
+            MessageDescriptor {
+                name: full_name,
+                fields,
+            },
+        );
+        Ok(())
+    }
+
+    fn add_enum(&mut self, scope: &str, descriptor: &[u8]) -> Result<(), String> {
+        let mut name = String::new();
+        let mut values = HashMap::new();
+        for (number, value) in wire_fields(descriptor)? {
+            match (number, value) {
+                (1, value) => name = wire_string(value)?,
+                (2, WireValue::Bytes(enum_value)) => {
+                    let mut value_name = String::new();
+                    let mut value_number = 0;
+                    for (number, value) in wire_fields(enum_value)? {
+                        match number {
+                            1 => value_name = wire_string(value)?,
+                            2 => value_number = wire_varint(value)? as i32 as i64,
+                            _ => {}
+                        }
+                    }
+                    values.insert(value_number, value_name);
+                }
+                _ => {}
+            }
+        }
+        self.enums.insert(qualified_name(scope, &name), values);
+        Ok(())
+    }
+
+    fn decode_message(&self, name: &str, raw: &[u8], depth: usize) -> Result<FieldValue, String> {
+        if depth > MAX_MESSAGE_DEPTH {
+            return Err(format!("{} is nested too deep", name));
+        }
+        let message = self
+            .messages
+            .get(name)
+            .ok_or_else(|| format!("no schema for message {}", name))?;
+        let mut values: HashMap<u32, Vec<FieldValue>> = HashMap::new();
+        for (number, value) in wire_fields(raw)? {
+            //fields of newer schema versions are skipped
+            let field = match message.fields.iter().find(|field| field.number == number) {
+                Some(field) => field,
+                None => continue,
+            };
+            let decoded = values.entry(number).or_default();
+            match value {
+                WireValue::Bytes(packed) if field.repeated && field.packable() => {
+                    let mut reader = WireReader::new(packed);
+                    while !reader.is_done() {
+                        let value = match field.field_type {
+                            field_type::DOUBLE | field_type::FIXED64 | field_type::SFIXED64 => {
+                                WireValue::Fixed64(reader.fixed64()?)
+                            }
+                            field_type::FLOAT | field_type::FIXED32 | field_type::SFIXED32 => {
+                                WireValue::Fixed32(reader.fixed32()?)
+                            }
+                            _ => WireValue::Varint(reader.varint()?),
+                        };
+                        decoded.push(self.decode_value(field, value, depth)?);
+                    }
+                }
+                value => decoded.push(self.decode_value(field, value, depth)?),
+            }
+        }
+
+        let mut fields = Vec::with_capacity(message.fields.len());
+        for field in &message.fields {
+            let mut decoded = values.remove(&field.number).unwrap_or_default();
+            let value = if field.repeated {
+                FieldValue::array(field.scalar_type(), decoded)
+            } else {
+                //the last value wins, like in every protobuf implementation
+                match decoded.pop() {
+                    Some(value) => value,
+                    None => self.default_value(field),
+                }
+            };
+            fields.push((field.name.clone(), value));
+        }
+        Ok(FieldValue::Fields(fields))
+    }
+
+    fn decode_value(
+        &self,
+        field: &FieldDescriptor,
+        value: WireValue,
+        depth: usize,
+    ) -> Result<FieldValue, String> {
+        let mismatch = || {
+            format!(
+                "{} has type {} but got {:?}",
+                field.name, field.field_type, value
+            )
+        };
+        let value = match (field.field_type, value) {
+            (field_type::DOUBLE, WireValue::Fixed64(bits)) => {
+                MushroomValue::Double(f64::from_bits(bits))
+            }
+            (field_type::FLOAT, WireValue::Fixed32(bits)) => {
+                MushroomValue::Float(f32::from_bits(bits) as f64)
+            }
+            //uint64 wraps around like it does in WPILib, there are no unsigned values
+            (field_type::INT64 | field_type::UINT64, WireValue::Varint(value)) => {
+                MushroomValue::Int(value as i64)
+            }
+            (field_type::INT32, WireValue::Varint(value)) => {
+                MushroomValue::Int(value as i32 as i64)
+            }
+            (field_type::UINT32, WireValue::Varint(value)) => {
+                MushroomValue::Int(value as u32 as i64)
+            }
+            (field_type::SINT32, WireValue::Varint(value)) => {
+                MushroomValue::Int(((value >> 1) as i64 ^ -((value & 1) as i64)) as i32 as i64)
+            }
+            (field_type::SINT64, WireValue::Varint(value)) => {
+                MushroomValue::Int((value >> 1) as i64 ^ -((value & 1) as i64))
+            }
+            (field_type::FIXED64 | field_type::SFIXED64, WireValue::Fixed64(value)) => {
+                MushroomValue::Int(value as i64)
+            }
+            (field_type::FIXED32, WireValue::Fixed32(value)) => MushroomValue::Int(value as i64),
+            (field_type::SFIXED32, WireValue::Fixed32(value)) => {
+                MushroomValue::Int(value as i32 as i64)
+            }
+            (field_type::BOOL, WireValue::Varint(value)) => MushroomValue::Boolean(value != 0),
+            (field_type::ENUM, WireValue::Varint(value)) => {
+                self.enum_value(field, value as i32 as i64)
+            }
+            (field_type::STRING, WireValue::Bytes(bytes)) => {
+                MushroomValue::String(String::from_utf8_lossy(bytes).into_owned())
+            }
+            (field_type::BYTES, WireValue::Bytes(bytes)) => {
+                MushroomValue::ByteArray(bytes.to_vec())
+            }
+            (field_type::MESSAGE, WireValue::Bytes(bytes)) => {
+                let name = field.type_name.as_deref().ok_or_else(mismatch)?;
+                return self.decode_message(name, bytes, depth + 1);
+            }
+            _ => return Err(mismatch()),
+        };
+        Ok(FieldValue::Value(value))
+    }
+
+    /// Proto3 leaves out fields with their default value, unset messages are null
+    fn default_value(&self, field: &FieldDescriptor) -> FieldValue {
+        FieldValue::Value(match field.field_type {
+            field_type::DOUBLE => MushroomValue::Double(0.0),
+            field_type::FLOAT => MushroomValue::Float(0.0),
+            field_type::BOOL => MushroomValue::Boolean(false),
+            field_type::STRING => MushroomValue::String(String::new()),
+            field_type::BYTES => MushroomValue::ByteArray(Vec::new()),
+            field_type::ENUM => self.enum_value(field, 0),
+            field_type::MESSAGE | field_type::GROUP => return FieldValue::Null,
+            _ => MushroomValue::Int(0),
+        })
+    }
+
+    /// The name of the enum value, the number if the schema has no name for it
+    fn enum_value(&self, field: &FieldDescriptor, value: i64) -> MushroomValue {
+        MushroomValue::String(
+            field
+                .type_name
+                .as_ref()
+                .and_then(|type_name| self.enums.get(type_name))
+                .and_then(|values| values.get(&value))
+                .cloned()
+                .unwrap_or_else(|| value.to_string()),
+        )
+    }
+}
+
+fn qualified_name(scope: &str, name: &str) -> String {
+    if scope.is_empty() {
+        String::from(name)
+    } else {
+        format!("{}.{}", scope, name)
+    }
+}
@@ -0,0 +1,430 @@
+use std::collections::HashMap;
+
+use super::{FieldValue, ScalarType};
+use crate::mushroom_types::MushroomValue;
+
+/// Type prefix of NT4 topics holding WPILib structs, e.g. `struct:Pose2d`
+pub const STRUCT_TYPE_PREFIX: &str = "struct:";
+
+/// Nested structs deeper than this are assumed to reference themselves
+const MAX_STRUCT_DEPTH: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FieldKind {
+    Bool,
+    Char,
+    Int(u8),
+    UInt(u8),
+    Float,
+    Double,
+    Struct(String),
+}
+
+impl FieldKind {
+    fn parse(type_name: &str) -> Self {
+        match type_name {
+            "bool" => FieldKind::Bool,
+            "char" => FieldKind::Char,
+            "int8" => FieldKind::Int(1),
+            "int16" => FieldKind::Int(2),
+            "int32" => FieldKind::Int(4),
+            "int64" => FieldKind::Int(8),
+            "uint8" => FieldKind::UInt(1),
+            "uint16" => FieldKind::UInt(2),
+            "uint32" => FieldKind::UInt(4),
+            "uint64" => FieldKind::UInt(8),
+            "float" | "float32" => FieldKind::Float,
+            "double" | "float64" => FieldKind::Double,
+            other => FieldKind::Struct(String::from(other)),
+        }
+    }
+
+    /// `None` for structs, their size depends on their schema
+    fn size(&self) -> Option<usize> {
+        match self {
+            FieldKind::Bool | FieldKind::Char => Some(1),
+            FieldKind::Int(size) | FieldKind::UInt(size) => Some(*size as usize),
+            FieldKind::Float => Some(4),
+            FieldKind::Double => Some(8),
+            FieldKind::Struct(_) => None,
+        }
+    }
+
+    fn is_integer(&self) -> bool {
+        matches!(self, FieldKind::Int(_) | FieldKind::UInt(_))
+    }
+}
+
+/// One declaration of a struct schema, e.g. `enum {a=1, b=2} int8 mode : 4`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StructField {
+    pub name: String,
+    pub kind: FieldKind,
+    /// The length of array fields
+    pub count: Option<usize>,
+    /// The width of bit-fields
+    pub bits: Option<u8>,
+    pub enum_values: Option<HashMap<i64, String>>,
+}
+
+impl StructField {
+    fn parse(declaration: &str) -> Result<Self, String> {
+        let mut rest = declaration.trim();
+        let mut enum_values = None;
+        if let Some(after_enum) = rest.strip_prefix("enum") {
+            let after_enum = after_enum.trim_start();
+            let body = after_enum
+                .strip_prefix('{')
+                .and_then(|body| body.split_once('}'))
+                .ok_or_else(|| format!("malformed enum in `{}`", declaration))?;
+            enum_values = Some(parse_enum(body.0)?);
+            rest = body.1.trim();
+        }
+
+        let (declaration_part, bits) = match rest.split_once(':') {
+            Some((part, bits)) => {
+                let bits = bits
+                    .trim()
+                    .parse::<u8>()
+                    .map_err(|_| format!("bad bit width in `{}`", declaration))?;
+                (part.trim(), Some(bits))
+            }
+            None => (rest, None),
+        };
+        let (type_name, name_part) = declaration_part
+            .split_once(char::is_whitespace)
+            .ok_or_else(|| format!("missing field name in `{}`", declaration))?;
+        let name_part = name_part.trim();
+        let (name, count) = match name_part.split_once('[') {
+            Some((name, count)) => {
+                let count = count
+                    .trim()
+                    .strip_suffix(']')
+                    .and_then(|count| count.trim().parse::<usize>().ok())
+                    .ok_or_else(|| format!("bad array length in `{}`", declaration))?;
+                (name.trim(), Some(count))
+            }
+            None => (name_part, None),
+        };
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(format!("bad field name in `{}`", declaration));
+        }
+
+        let kind = FieldKind::parse(type_name.trim());
+        if enum_values.is_some() && !kind.is_integer() {
+            return Err(format!("enum on a non integer field in `{}`", declaration));
+        }
+        if let Some(bits) = bits {
+            let max_bits = match kind {
+                FieldKind::Bool => 1,
+                FieldKind::Int(size) | FieldKind::UInt(size) => size * 8,
+                _ => 0,
+            };
+            if count.is_some() || bits == 0 || bits > max_bits {
+                return Err(format!("bad bit-field in `{}`", declaration));
+            }
+        }
+        Ok(Self {
+            name: String::from(name),
+            kind,
+            count,
+            bits,
+            enum_values,
+        })
+    }
+
+    /// `None` for nested structs
+    fn scalar_type(&self) -> Option<ScalarType> {
+        if self.enum_values.is_some() {
+            return Some(ScalarType::String);
+        }
+        match self.kind {
+            FieldKind::Bool => Some(ScalarType::Boolean),
+            FieldKind::Char => Some(ScalarType::String),
+            FieldKind::Int(_) | FieldKind::UInt(_) => Some(ScalarType::Int),
+            FieldKind::Float => Some(ScalarType::Float),
+            FieldKind::Double => Some(ScalarType::Double),
+            FieldKind::Struct(_) => None,
+        }
+    }
+}
+
+fn parse_enum(body: &str) -> Result<HashMap<i64, String>, String> {
+    body.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(|item| {
+            let (name, value) = item
+                .split_once('=')
+                .ok_or_else(|| format!("enum value without a number `{}`", item))?;
+            let value = value
+                .trim()
+                .parse::<i64>()
+                .map_err(|_| format!("bad enum value `{}`", item))?;
+            Ok((value, String::from(name.trim())))
+        })
+        .collect()
+}
+
+/// A parsed WPILib struct schema, e.g. `Translation2d translation; Rotation2d rotation`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StructSchema {
+    pub name: String,
+    pub fields: Vec<StructField>,
+}
+
+impl StructSchema {
+    pub fn parse(name: &str, schema: &str) -> Result<Self, String> {
+        let fields = schema
+            .split(';')
+            .filter(|declaration| !declaration.trim().is_empty())
+            .map(StructField::parse)
+            .collect::<Result<Vec<StructField>, String>>()?;
+        Ok(Self {
+            name: String::from(name),
+            fields,
+        })
+    }
+}
+
+/// Where a field sits in the packed bytes of its struct
+#[derive(Debug)]
+struct FieldLayout<'a> {
+    field: &'a StructField,
+    offset: usize,
+    /// Size of the field, or of the storage unit of a bit-field
+    size: usize,
+    /// Shift of a bit-field inside its storage unit
+    shift: u8,
+}
+
+/// The storage unit bit-fields are currently packed into
+struct BitUnit {
+    offset: usize,
+    size: usize,
+    used: u8,
+}
+
+/// Every struct schema published under `/.schema`, keyed by struct name without the prefix
+#[derive(Debug, Clone, Default)]
+pub struct StructRegistry {
+    schemas: HashMap<String, StructSchema>,
+}
+
+impl StructRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `type_name` is the name of the schema topic without `/.schema/`, e.g. `struct:Pose2d`
+    pub fn add(&mut self, type_name: &str, schema: &str) -> Result<(), String> {
+        let name = type_name
+            .strip_prefix(STRUCT_TYPE_PREFIX)
+            .unwrap_or(type_name);
+        let schema = StructSchema::parse(name, schema)?;
+        self.schemas.insert(String::from(name), schema);
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&StructSchema> {
+        self.schemas.get(name)
+    }
+
+    /// Fails if the schema of the struct or of any struct nested in it is missing
+    pub fn size(&self, name: &str) -> Result<usize, String> {
+        self.layout(name, 0).map(|(_, size)| size)
+    }
+
+    /// Decodes a value of a `struct:Name` or `struct:Name[]` topic
+    pub fn decode(&self, type_name: &str, raw: &[u8]) -> Result<serde_json::Value, String> {
+        self.decode_fields(type_name, raw)
+            .map(|fields| fields.to_json())
+    }
+
+    /// Like [`StructRegistry::decode`] but keeps the declared type of every field
+    pub fn decode_fields(&self, type_name: &str, raw: &[u8]) -> Result<FieldValue, String> {
+        let name = type_name
+            .strip_prefix(STRUCT_TYPE_PREFIX)
+            .ok_or_else(|| format!("{} is not a struct type", type_name))?;
+        match name.strip_suffix("[]") {
+            Some(name) => {
+                let size = self.size(name)?;
+                if size == 0 || raw.len() % size != 0 {
+                    return Err(format!(
+                        "{} bytes are not a whole number of {}",
+                        raw.len(),
+                        name
+                    ));
+                }
+                raw.chunks(size)
+                    .map(|chunk| self.decode_struct(name, chunk, 0))
+                    .collect::<Result<Vec<FieldValue>, String>>()
+                    .map(FieldValue::Array)
+            }
+            None => {
+                let size = self.size(name)?;
+                if raw.len() != size {
+                    return Err(format!("{} is {} bytes, got {}", name, size, raw.len()));
+                }
+                self.decode_struct(name, raw, 0)
+            }
+        }
+    }
+
+    fn schema(&self, name: &str, depth: usize) -> Result<&StructSchema, String> {
+        if depth > MAX_STRUCT_DEPTH {
+            return Err(format!("{} is nested too deep", name));
+        }
+        self.schemas
+            .get(name)
+            .ok_or_else(|| format!("no schema for struct {}", name))
+    }
+
+    fn layout(&self, name: &str, depth: usize) -> Result<(Vec<FieldLayout<'_>>, usize), String> {
+        let schema = self.schema(name, depth)?;
+        let mut layouts = Vec::with_capacity(schema.fields.len());
+        let mut offset = 0;
+        let mut unit: Option<BitUnit> = None;
+        for field in &schema.fields {
+            let size = match &field.kind {
+                FieldKind::Struct(name) => self.layout(name, depth + 1)?.1,
+                kind => kind.size().unwrap_or_default(),
+            };
+            match field.bits {
+                Some(bits) => {
+                    //bools go into whatever unit is open, other bit-fields need one of their size
+                    let fits = unit.as_ref().map_or(false, |unit| {
+                        (field.kind == FieldKind::Bool || unit.size == size)
+                            && unit.used as usize + bits as usize <= unit.size * 8
+                    });
+                    if !fits {
+                        unit = Some(BitUnit {
+                            offset,
+                            size,
+                            used: 0,
+                        });
+                        offset += size;
+                    }
+                    let unit = unit.as_mut().unwrap();
+                    layouts.push(FieldLayout {
+                        field,
+                        offset: unit.offset,
+                        size: unit.size,
+                        shift: unit.used,
+                    });
+                    unit.used += bits;
+                }
+                None => {
+                    unit = None;
+                    layouts.push(FieldLayout {
+                        field,
+                        offset,
+                        size,
+                        shift: 0,
+                    });
+                    offset += size * field.count.unwrap_or(1);
+                }
+            }
+        }
+        Ok((layouts, offset))
+    }
+
+    fn decode_struct(&self, name: &str, raw: &[u8], depth: usize) -> Result<FieldValue, String> {
+        let (layouts, _) = self.layout(name, depth)?;
+        let mut fields = Vec::with_capacity(layouts.len());
+        for layout in layouts {
+            let field = layout.field;
+            let value = match (field.count, &field.kind) {
+                (Some(count), FieldKind::Char) => {
+                    let bytes = &raw[layout.offset..layout.offset + count];
+                    let end = bytes.iter().position(|byte| *byte == 0).unwrap_or(count);
+                    let value = String::from_utf8_lossy(&bytes[..end]).into_owned();
+                    FieldValue::Value(MushroomValue::String(value))
+                }
+                (Some(count), _) => (0..count)
+                    .map(|index| {
+                        let offset = layout.offset + index * layout.size;
+                        self.decode_field(field, &raw[offset..offset + layout.size], depth)
+                    })
+                    .collect::<Result<Vec<FieldValue>, String>>()
+                    .map(|elements| FieldValue::array(field.scalar_type(), elements))?,
+                (None, _) => {
+                    let bytes = &raw[layout.offset..layout.offset + layout.size];
+                    match field.bits {
+                        Some(bits) => decode_bits(field, bytes, layout.shift, bits),
+                        None => self.decode_field(field, bytes, depth)?,
+                    }
+                }
+            };
+            fields.push((field.name.clone(), value));
+        }
+        Ok(FieldValue::Fields(fields))
+    }
+
+    fn decode_field(
+        &self,
+        field: &StructField,
+        bytes: &[u8],
+        depth: usize,
+    ) -> Result<FieldValue, String> {
+        let value = match &field.kind {
+            FieldKind::Bool => MushroomValue::Boolean(bytes[0] != 0),
+            FieldKind::Char => {
+                MushroomValue::String(String::from_utf8_lossy(&bytes[..1]).into_owned())
+            }
+            FieldKind::Int(size) => {
+                let shift = 64 - *size as u32 * 8;
+                let value = ((read_le(bytes) << shift) as i64) >> shift;
+                enum_value(field, value)
+            }
+            //uint64 wraps around like it does in WPILib, there are no unsigned values
+            FieldKind::UInt(_) => enum_value(field, read_le(bytes) as i64),
+            FieldKind::Float => {
+                MushroomValue::Float(f32::from_le_bytes(bytes.try_into().unwrap()) as f64)
+            }
+            FieldKind::Double => {
+                MushroomValue::Double(f64::from_le_bytes(bytes.try_into().unwrap()))
+            }
+            FieldKind::Struct(name) => return self.decode_struct(name, bytes, depth + 1),
+        };
+        Ok(FieldValue::Value(value))
+    }
+}
+
+fn read_le(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .rev()
+        .fold(0, |value, byte| (value << 8) | *byte as u64)
+}
+
+fn decode_bits(field: &StructField, unit: &[u8], shift: u8, bits: u8) -> FieldValue {
+    let mask = if bits == 64 {
+        u64::MAX
+    } else {
+        (1 << bits) - 1
+    };
+    let value = (read_le(unit) >> shift) & mask;
+    FieldValue::Value(match field.kind {
+        FieldKind::Bool => MushroomValue::Boolean(value != 0),
+        FieldKind::Int(_) => {
+            //sign extends from the top bit of the field
+            let unused = 64 - bits as u32;
+            enum_value(field, ((value << unused) as i64) >> unused)
+        }
+        _ => enum_value(field, value as i64),
+    })
+}
+
+/// The name of the enum value for fields with an enum, the number if it has no name
+fn enum_value(field: &StructField, value: i64) -> MushroomValue {
+    match &field.enum_values {
+        Some(values) => MushroomValue::String(
+            values
+                .get(&value)
+                .cloned()
+                .unwrap_or_else(|| value.to_string()),
+        ),
+        None => MushroomValue::Int(value),
+    }
+}
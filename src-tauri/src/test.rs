@@ -2,6 +2,1494 @@
 //
 
 #[test]
+#[allow(clippy::assertions_on_constants)]
 fn test_test() {
     assert!(true)
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_team_candidates() {
+    use crate::networktable::discovery::team_candidates;
+
+    assert_eq!(
+        team_candidates(254),
+        vec!["10.2.54.2", "roboRIO-254-FRC.local", "172.22.11.2", "127.0.0.1"]
+    );
+    assert_eq!(team_candidates(9999)[0], "10.99.99.2");
+    assert_eq!(team_candidates(12)[0], "10.0.12.2");
+}
+
+#[test]
+fn test_client_id_display() {
+    use crate::networktable::handler::NetworkTableClientId;
+
+    let id = NetworkTableClientId::new("10.2.54.2".into(), 5810, "dash".into());
+    assert_eq!(id.repr(), "10.2.54.2:5810:dash");
+    let id = NetworkTableClientId::new("::1".into(), 5810, "dash".into());
+    assert_eq!(id.repr(), "[::1]:5810:dash");
+    let id = NetworkTableClientId::new("sim.local".into(), 5810, "dash".into());
+    assert_eq!(id.repr(), "sim.local:5810:dash");
+    let id = NetworkTableClientId::new_for_team(254, 5810, "dash".into());
+    assert_eq!(id.repr(), "team254:5810:dash");
+    let id = NetworkTableClientId::new("2001:db8::2".into(), 1735, "Enoki 1".into());
+    assert_eq!(id.to_string(), "[2001:db8::2]:1735:Enoki 1");
+}
+
+#[test]
+fn test_network_table_update() {
+    use crate::mushroom_types::{MushroomEntry, MushroomTable, MushroomValue};
+    use crate::networktable::handler::{NetworkTableClientId, NetworkTableUpdate};
+    use crate::time::ServerTime;
+
+    //the frontend matches updates to clients by the serialized id
+    let id = NetworkTableClientId::new("10.2.54.2".into(), 5810, "dash".into());
+    let json = serde_json::to_value(&id).unwrap();
+    assert_eq!(json["host"], "10.2.54.2");
+    assert_eq!(json["port"], 5810);
+    assert_eq!(json["identity"], "dash");
+    assert_eq!(
+        serde_json::from_value::<NetworkTableClientId>(json).unwrap(),
+        id
+    );
+
+    let mut entries = MushroomTable::new(ServerTime(5));
+    entries.add_entry(MushroomEntry::new(
+        MushroomValue::Double(1.5),
+        "/speed".into(),
+        Some(ServerTime(5)),
+    ));
+    let update = NetworkTableUpdate::new(id, entries, vec!["/old".into()]);
+    let json = serde_json::to_value(&update).unwrap();
+    assert_eq!(json["client_id"]["identity"], "dash");
+    assert_eq!(json["entries"][0]["path"], "/speed");
+    assert_eq!(json["entries"][0]["value"]["value"], 1.5);
+    assert_eq!(json["entries"][0]["timestamp"], 5);
+    assert_eq!(json["removed"], serde_json::json!(["/old"]));
+}
+
+#[test]
+fn test_connection_status() {
+    use crate::networktable::connection::{ConnectionState, ConnectionStatus};
+
+    let mut status = ConnectionStatus::new();
+    assert_eq!(status.state(), ConnectionState::Connecting);
+    assert_eq!(status.previous(), None);
+    assert!(status.transition(ConnectionState::Connected));
+    assert_eq!(status.previous(), Some(ConnectionState::Connecting));
+    //staying in a state is not a transition and keeps the previous one
+    assert!(!status.transition(ConnectionState::Connected));
+    assert_eq!(status.previous(), Some(ConnectionState::Connecting));
+
+    for state in [
+        ConnectionState::Reconnecting,
+        ConnectionState::Failed,
+        ConnectionState::Reconnecting,
+        ConnectionState::Connected,
+        ConnectionState::Stopped,
+    ] {
+        let before = status.state();
+        assert!(status.transition(state));
+        assert_eq!(status.state(), state);
+        assert_eq!(status.previous(), Some(before));
+    }
+    let json = serde_json::to_value(&status).unwrap();
+    assert_eq!(json["state"], "Stopped");
+    assert_eq!(json["previous"], "Connected");
+}
+
+#[test]
+fn test_connection_error() {
+    use crate::error::EnokiError;
+    use crate::networktable::connection::{ConnectionState, ConnectionStatus};
+
+    let err = EnokiError::NTConnect(String::from("10.0.0.2:5810: refused"));
+    assert_eq!(
+        serde_json::to_value(&err).unwrap(),
+        serde_json::json!({ "NTConnect": "10.0.0.2:5810: refused" })
+    );
+
+    let mut status = ConnectionStatus::new();
+    assert_eq!(status.last_error(), None);
+    let reported = status.record_error(&err);
+    assert_eq!(reported, err.to_string());
+    assert_eq!(status.last_error(), Some(reported.as_str()));
+    //the error outlives the transitions that follow it
+    status.transition(ConnectionState::Reconnecting);
+    let json = serde_json::to_value(&status).unwrap();
+    assert_eq!(json["last_error"], reported.as_str());
+
+    let reported = status.record_error(&EnokiError::NTPublish(String::from("/a: closed")));
+    assert_eq!(status.last_error(), Some(reported.as_str()));
+}
+
+#[test]
+fn test_topic_info() {
+    use crate::networktable::topics::{type_string, TopicInfo, TopicProperties};
+    use network_tables::v4::{Topic, Type};
+
+    assert_eq!(type_string(&Type::Double), "double");
+    assert_eq!(type_string(&Type::DoubleArray), "double[]");
+    assert_eq!(type_string(&Type::StringArray), "string[]");
+    assert_eq!(type_string(&Type::Json), "json");
+
+    let topic = Topic {
+        name: String::from("/SmartDashboard/pose"),
+        id: 7,
+        pubuid: Some(3),
+        r#type: Type::DoubleArray,
+        properties: Some(
+            TopicProperties {
+                persistent: Some(true),
+                retained: None,
+                cached: Some(false),
+            }
+            .into(),
+        ),
+    };
+    let info = serde_json::to_value(TopicInfo::from(&topic)).unwrap();
+    assert_eq!(info["name"], "/SmartDashboard/pose");
+    assert_eq!(info["id"], 7);
+    assert_eq!(info["type"], "double[]");
+    assert_eq!(info["publisher"], 3);
+    assert_eq!(info["properties"]["persistent"], true);
+    assert_eq!(info["properties"]["cached"], false);
+
+    let info = serde_json::to_value(TopicInfo::new(
+        String::from("/log/value"),
+        1,
+        String::from("int"),
+    ))
+    .unwrap();
+    assert_eq!(info["type"], "int");
+    assert_eq!(info["properties"], serde_json::json!({}));
+    assert_eq!(info["publisher"], serde_json::Value::Null);
+}
+
+#[test]
+fn test_table_remove_entry() {
+    use crate::mushroom_types::{MushroomEntry, MushroomTable, MushroomValue};
+    use crate::time::ServerTime;
+
+    let mut table = MushroomTable::new(ServerTime(0));
+    for path in ["/a", "/b", "/c"] {
+        table.add_entry(MushroomEntry::new(MushroomValue::Int(1), path.into(), None));
+    }
+    assert!(table.remove_entry(&"/a".into()).is_some());
+    assert!(table.remove_entry(&"/a".into()).is_none());
+    assert_eq!(table.len(), 2);
+    //the entry moved into the freed slot has to stay reachable
+    assert!(table.get_entry(&"/c".into()).is_some());
+    table.add_entry(MushroomEntry::new(MushroomValue::Int(2), "/c".into(), None));
+    assert_eq!(table.len(), 2);
+    assert_eq!(
+        table.get_entry(&"/c".into()).unwrap().get_value(),
+        MushroomValue::Int(2)
+    );
+}
+
+#[test]
+fn test_value_from_json() {
+    use crate::mushroom_types::MushroomValue;
+    use serde_json::json;
+
+    assert_eq!(
+        MushroomValue::from_json("Double", json!(1.5)),
+        Ok(MushroomValue::Double(1.5))
+    );
+    assert_eq!(
+        MushroomValue::from_json("IntArray", json!([1, 2])),
+        Ok(MushroomValue::IntArray(vec![1, 2]))
+    );
+    assert!(MushroomValue::from_json("Int", json!(1.5)).is_err());
+    assert!(MushroomValue::from_json("Boolean", json!("true")).is_err());
+    assert!(MushroomValue::from_json("Quaternion", json!(1)).is_err());
+}
+
+#[test]
+fn test_topic_properties() {
+    use crate::networktable::topics::TopicProperties;
+    use network_tables::v4::PublishProperties;
+
+    let properties: TopicProperties =
+        serde_json::from_str(r#"{"persistent": true, "cached": false}"#).unwrap();
+    assert_eq!(properties.persistent, Some(true));
+    assert_eq!(properties.retained, None);
+    let nt_properties = PublishProperties::from(properties);
+    assert_eq!(nt_properties.persistent, Some(true));
+    assert_eq!(
+        nt_properties.rest.as_ref().unwrap().get("cached"),
+        Some(&serde_json::Value::Bool(false))
+    );
+    //cached goes on the wire next to the known properties
+    assert_eq!(
+        serde_json::to_value(&nt_properties).unwrap(),
+        serde_json::json!({ "persistent": true, "retained": null, "cached": false })
+    );
+    let nt_properties = PublishProperties::from(TopicProperties::default());
+    assert_eq!(nt_properties.persistent, None);
+    assert_eq!(nt_properties.retained, None);
+    assert!(nt_properties.rest.is_none());
+    //a setproperties update deletes what isn't set
+    let update = TopicProperties {
+        retained: Some(true),
+        ..Default::default()
+    }
+    .update();
+    assert_eq!(update.get("retained"), Some(&serde_json::Value::Bool(true)));
+    assert_eq!(update.get("persistent"), Some(&serde_json::Value::Null));
+    assert_eq!(update.get("cached"), Some(&serde_json::Value::Null));
+}
+
+#[test]
+fn test_publish_batch() {
+    use crate::mushroom_types::{MushroomPath, MushroomValue};
+    use crate::networktable::commands::{publish_batch, PublishItem};
+
+    //type and value sit next to the path like a serialized MushroomValue
+    let items: Vec<PublishItem> = serde_json::from_str(
+        r#"[
+            {"path": "/SmartDashboard/speed", "type": "Double", "value": 1.5},
+            {"path": "/SmartDashboard/names", "type": "StringArray", "value": ["a", "b"],
+                "properties": {"persistent": true}}
+        ]"#,
+    )
+    .unwrap();
+    let (table, properties) = publish_batch(items).unwrap();
+    assert_eq!(table.len(), 2);
+    let speed = MushroomPath::from("/SmartDashboard/speed");
+    assert!(table.has_entry(&speed));
+    assert_eq!(
+        table
+            .get_entries()
+            .iter()
+            .find(|entry| entry.get_path() == speed)
+            .map(|entry| entry.get_value()),
+        Some(MushroomValue::Double(1.5))
+    );
+    assert_eq!(properties.len(), 1);
+    assert_eq!(properties["/SmartDashboard/names"].persistent, Some(true));
+
+    let items: Vec<PublishItem> = serde_json::from_str(
+        r#"[{"path": "/a", "type": "Int", "value": 1}, {"path": "/a", "type": "Int", "value": 2}]"#,
+    )
+    .unwrap();
+    assert!(publish_batch(items).is_err());
+    let items: Vec<PublishItem> =
+        serde_json::from_str(r#"[{"path": "", "type": "Boolean", "value": true}]"#).unwrap();
+    assert!(publish_batch(items).is_err());
+    assert!(serde_json::from_str::<Vec<PublishItem>>(
+        r#"[{"path": "/a", "type": "Int", "value": 1.5}]"#
+    )
+    .is_err());
+    assert!(serde_json::from_str::<Vec<PublishItem>>(r#"[{"path": "/a", "value": 1}]"#).is_err());
+}
+
+#[test]
+fn test_topic_history() {
+    use crate::mushroom_types::{MushroomEntry, MushroomValue};
+    use crate::networktable::history::{HistoryConfig, TopicHistory};
+    use crate::time::ServerTime;
+
+    let history = TopicHistory::new(HistoryConfig {
+        depth: 3,
+        window: None,
+    });
+    for i in 0..5 {
+        history.record(&MushroomEntry::new(
+            MushroomValue::Int(i),
+            "/a".into(),
+            Some(ServerTime(i as u64 * 10)),
+        ));
+    }
+    history.record(&MushroomEntry::new(MushroomValue::Int(9), "/a".into(), None));
+    //only the newest three are kept
+    let samples = history.since(&"/a".into(), None);
+    assert_eq!(samples.len(), 3);
+    assert_eq!(samples[0].get_value(), MushroomValue::Int(2));
+    assert_eq!(history.since(&"/a".into(), Some(ServerTime(30))).len(), 1);
+    assert!(history.since(&"/b".into(), None).is_empty());
+
+    history.set_config(HistoryConfig {
+        depth: 3,
+        window: Some(15),
+    });
+    let samples = history.since(&"/a".into(), None);
+    assert_eq!(samples.len(), 2);
+    assert_eq!(samples[0].get_value(), MushroomValue::Int(3));
+    //a window longer than the clock keeps everything
+    history.set_config(HistoryConfig {
+        depth: 3,
+        window: Some(u64::MAX),
+    });
+    history.record(&MushroomEntry::new(
+        MushroomValue::Int(10),
+        "/a".into(),
+        Some(ServerTime(100)),
+    ));
+    assert_eq!(history.since(&"/a".into(), None).len(), 3);
+}
+
+#[test]
+fn test_datalog_type() {
+    use crate::datalog::handler::datalog_type;
+    use crate::mushroom_types::MushroomValue;
+
+    assert_eq!(datalog_type(&MushroomValue::Int(1)), "int64");
+    assert_eq!(
+        datalog_type(&MushroomValue::DoubleArray(vec![1.0])),
+        "double[]"
+    );
+    assert_eq!(datalog_type(&MushroomValue::ByteArray(vec![1])), "raw");
+    //protobuf values are logged as raw bytes instead of being dropped
+    assert_eq!(datalog_type(&MushroomValue::Protobuf(vec![1])), "raw");
+}
+
+#[test]
+fn test_unsubscribed_values() {
+    use crate::networktable::handler::{is_subscribed, SubscriptionPackage};
+    use network_tables::v4::{MessageData, SubscriptionOptions, Type};
+    use std::collections::HashMap;
+    use tokio::sync::mpsc::unbounded_channel;
+
+    let mut sub_requests = HashMap::from([
+        (
+            String::from("/a/"),
+            SubscriptionPackage::new(
+                String::from("/a/"),
+                SubscriptionOptions {
+                    prefix: Some(true),
+                    ..Default::default()
+                },
+            ),
+        ),
+        (
+            String::from("/b"),
+            SubscriptionPackage::new(String::from("/b"), Default::default()),
+        ),
+    ]);
+    let (values, mut queue) = unbounded_channel();
+    for topic in ["/a/x", "/b"] {
+        values
+            .send(MessageData {
+                topic_name: String::from(topic),
+                timestamp: 0,
+                r#type: Type::Int,
+                data: rmpv::Value::from(1),
+            })
+            .unwrap();
+    }
+    assert!(is_subscribed(sub_requests.values(), "/a/x"));
+
+    //the value for /a/x is still queued when the client unsubscribes
+    sub_requests.remove("/a/");
+    let mut received = Vec::new();
+    while let Ok(msg) = queue.try_recv() {
+        if is_subscribed(sub_requests.values(), &msg.topic_name) {
+            received.push(msg.topic_name);
+        }
+    }
+    assert_eq!(received, vec![String::from("/b")]);
+    assert!(!is_subscribed(sub_requests.values(), "/bc"));
+}
+
+#[test]
+fn test_link_metrics() {
+    use crate::networktable::metrics::MetricsTracker;
+    use std::time::Duration;
+
+    let metrics = MetricsTracker::new();
+    assert!(metrics.take_update().is_none());
+    metrics.record_rtt(Duration::from_millis(10));
+    assert_eq!(metrics.metrics().jitter, None);
+    metrics.record_rtt(Duration::from_millis(26));
+    assert_eq!(metrics.metrics().rtt, Some(26.0));
+    assert_eq!(metrics.metrics().jitter, Some(1.0));
+
+    metrics.count_in(10);
+    metrics.count_in(30);
+    metrics.count_out(5);
+    metrics.roll();
+    let update = metrics.take_update().unwrap();
+    assert!(update.messages_in > update.messages_out);
+    assert!(update.bytes_in > update.bytes_out);
+    assert!(metrics.take_update().is_none());
+
+    metrics.reset_link();
+    assert_eq!(metrics.metrics().rtt, None);
+}
+
+#[test]
+fn test_time_sync() {
+    use crate::networktable::metrics::MetricsTracker;
+    use crate::time::{ServerClock, ServerTime};
+    use std::time::Duration;
+
+    let metrics = MetricsTracker::new();
+    metrics.record_time_sync(Some(Duration::from_millis(2)), ServerClock { offset: 48_000 });
+    assert_eq!(metrics.metrics().rtt, Some(2.0));
+    assert_eq!(metrics.metrics().clock_offset, Some(48_000));
+    //a connection that hasn't measured a round trip yet still has a clock
+    metrics.record_time_sync(None, ServerClock { offset: 50_000 });
+    assert_eq!(metrics.metrics().rtt, Some(2.0));
+    assert_eq!(metrics.metrics().clock_offset, Some(50_000));
+
+    //the offset gives back the server time it was measured from
+    let clock = ServerClock::measure(ServerTime(1_000_000_000));
+    metrics.record_time_sync(None, clock);
+    let offset = metrics.metrics().clock_offset.unwrap();
+    let server_now = ServerClock { offset }.server_now();
+    assert!(server_now >= ServerTime(1_000_000_000));
+    assert!(server_now < ServerTime(1_001_000_000));
+}
+
+#[test]
+fn test_nt4_server_state() {
+    use crate::networktable::server::ServerState;
+    use tokio::sync::mpsc::unbounded_channel;
+    use tokio_tungstenite::tungstenite::Message;
+
+    fn text(message: Message) -> serde_json::Value {
+        match message {
+            Message::Text(text) => serde_json::from_str(&text).unwrap(),
+            other => panic!("Expected text, got {:?}", other),
+        }
+    }
+    fn binary(message: Message) -> rmpv::Value {
+        match message {
+            Message::Binary(data) => rmpv::decode::read_value(&mut data.as_slice()).unwrap(),
+            other => panic!("Expected binary, got {:?}", other),
+        }
+    }
+    fn encode(fields: Vec<rmpv::Value>) -> Vec<u8> {
+        let mut buf = Vec::new();
+        rmpv::encode::write_value(&mut buf, &rmpv::Value::Array(fields)).unwrap();
+        buf
+    }
+
+    let mut state = ServerState::new();
+    let (pub_sender, mut pub_messages) = unbounded_channel();
+    let (sub_sender, mut sub_messages) = unbounded_channel();
+    let publisher = state.add_client(String::from("sim"), pub_sender);
+    let subscriber = state.add_client(String::from("dashboard"), sub_sender);
+
+    state.handle_text(
+        subscriber,
+        r#"[{"method":"subscribe","params":{"topics":["/SmartDashboard/"],"subuid":1,"options":{"prefix":true}}}]"#,
+    );
+    state.handle_text(
+        publisher,
+        r#"[{"method":"publish","params":{"name":"/SmartDashboard/x","pubuid":7,"type":"double","properties":{}}}]"#,
+    );
+    //only the publisher learns its pubuid
+    assert_eq!(text(pub_messages.try_recv().unwrap())[0]["params"]["pubuid"], 7);
+    let announce = text(sub_messages.try_recv().unwrap());
+    assert_eq!(announce[0]["method"], "announce");
+    assert!(announce[0]["params"].get("pubuid").is_none());
+    let id = announce[0]["params"]["id"].as_i64().unwrap();
+
+    state.handle_binary(
+        publisher,
+        &encode(vec![7.into(), 100.into(), 1.into(), 2.5.into()]),
+    );
+    assert_eq!(
+        binary(sub_messages.try_recv().unwrap()),
+        rmpv::Value::Array(vec![id.into(), 100.into(), 1.into(), 2.5.into()])
+    );
+    assert!(pub_messages.try_recv().is_err());
+
+    //time sync echoes the client time back
+    state.handle_binary(
+        subscriber,
+        &encode(vec![(-1).into(), 0.into(), 2.into(), 1234.into()]),
+    );
+    match binary(sub_messages.try_recv().unwrap()) {
+        rmpv::Value::Array(fields) => {
+            assert_eq!(fields[0], rmpv::Value::from(-1));
+            assert_eq!(fields[3], rmpv::Value::from(1234));
+        }
+        other => panic!("Expected an array, got {:?}", other),
+    }
+
+    //a reused pubuid drops the topic it published before
+    state.handle_text(
+        publisher,
+        r#"[{"method":"publish","params":{"name":"/SmartDashboard/y","pubuid":7,"type":"double","properties":{}}}]"#,
+    );
+    assert_eq!(text(sub_messages.try_recv().unwrap())[0]["params"]["name"], "/SmartDashboard/x");
+    assert_eq!(text(sub_messages.try_recv().unwrap())[0]["params"]["name"], "/SmartDashboard/y");
+    assert_eq!(state.topic_names(), vec![String::from("/SmartDashboard/y")]);
+
+    state.remove_client(publisher);
+    assert_eq!(text(sub_messages.try_recv().unwrap())[0]["method"], "unannounce");
+    assert!(state.topic_names().is_empty());
+    assert_eq!(state.client_names(), vec![String::from("dashboard")]);
+}
+
+#[test]
+fn test_nt4_server_types() {
+    use crate::networktable::server::ServerState;
+    use tokio::sync::mpsc::unbounded_channel;
+    use tokio_tungstenite::tungstenite::Message;
+
+    fn text(message: Message) -> serde_json::Value {
+        match message {
+            Message::Text(text) => serde_json::from_str(&text).unwrap(),
+            other => panic!("Expected text, got {:?}", other),
+        }
+    }
+    fn value(pubuid: i64, type_id: u64, value: rmpv::Value) -> Vec<u8> {
+        let mut buf = Vec::new();
+        let fields = vec![pubuid.into(), 100.into(), type_id.into(), value];
+        rmpv::encode::write_value(&mut buf, &rmpv::Value::Array(fields)).unwrap();
+        buf
+    }
+    fn publish(name: &str, pubuid: i64, topic_type: &str, properties: &str) -> String {
+        format!(
+            r#"[{{"method":"publish","params":{{"name":"{}","pubuid":{},"type":"{}","properties":{}}}}}]"#,
+            name, pubuid, topic_type, properties
+        )
+    }
+
+    let mut state = ServerState::new();
+    let (first_sender, mut first_messages) = unbounded_channel();
+    let (second_sender, mut second_messages) = unbounded_channel();
+    let (sub_sender, mut sub_messages) = unbounded_channel();
+    let first = state.add_client(String::from("sim"), first_sender);
+    let second = state.add_client(String::from("other"), second_sender);
+    let subscriber = state.add_client(String::from("dashboard"), sub_sender);
+    state.handle_text(
+        subscriber,
+        r#"[{"method":"subscribe","params":{"topics":[""],"subuid":1,"options":{"prefix":true}}}]"#,
+    );
+
+    //values that don't match the published type are dropped
+    state.handle_text(first, &publish("/speed", 1, "double", "{}"));
+    first_messages.try_recv().unwrap();
+    sub_messages.try_recv().unwrap();
+    state.handle_binary(first, &value(1, 2, 3.into()));
+    assert!(sub_messages.try_recv().is_err());
+    state.handle_binary(first, &value(1, 1, 3.5.into()));
+    assert!(sub_messages.try_recv().is_ok());
+
+    //a topic can't be published with another type while it has a publisher
+    state.handle_text(second, &publish("/speed", 2, "int", "{}"));
+    assert!(second_messages.try_recv().is_err());
+    assert!(sub_messages.try_recv().is_err());
+    state.handle_binary(second, &value(2, 2, 3.into()));
+    assert!(sub_messages.try_recv().is_err());
+
+    //a retained topic without publishers takes the type it is published with next
+    state.handle_text(first, &publish("/mode", 3, "double", r#"{"retained":true}"#));
+    sub_messages.try_recv().unwrap();
+    state.handle_binary(first, &value(3, 1, 1.0.into()));
+    sub_messages.try_recv().unwrap();
+    state.handle_text(first, r#"[{"method":"unpublish","params":{"pubuid":3}}]"#);
+    assert!(state.topic_names().contains(&String::from("/mode")));
+    state.handle_text(second, &publish("/mode", 4, "string", "{}"));
+    assert_eq!(
+        text(second_messages.try_recv().unwrap())[0]["params"]["type"],
+        "string"
+    );
+    let announce = text(sub_messages.try_recv().unwrap());
+    assert_eq!(announce[0]["method"], "announce");
+    assert_eq!(announce[0]["params"]["type"], "string");
+    state.handle_binary(second, &value(4, 1, 2.0.into()));
+    assert!(sub_messages.try_recv().is_err());
+    state.handle_binary(second, &value(4, 4, "auto".into()));
+    assert!(sub_messages.try_recv().is_ok());
+}
+
+#[tokio::test]
+async fn test_nt4_server_end_to_end() {
+    use crate::networktable::server::NetworkTableServer;
+    use network_tables::v4::{Client, Config, SubscriptionOptions, Type};
+    use std::net::{Ipv4Addr, SocketAddr};
+    use std::time::Duration;
+
+    fn config() -> Config {
+        Config {
+            connect_timeout: 1000,
+            disconnect_retry_interval: 1000,
+            should_reconnect: Box::new(|_| false),
+            on_announce: Box::new(|_| Box::pin(async {})),
+            on_un_announce: Box::new(|_| Box::pin(async {})),
+            on_disconnect: Box::new(|| Box::pin(async {})),
+            on_reconnect: Box::new(|| Box::pin(async {})),
+        }
+    }
+
+    //port 0 lets the system pick, the server reports the port it got
+    let server = NetworkTableServer::bind(0, &tokio::runtime::Handle::current()).unwrap();
+    assert_ne!(server.port(), 0);
+    let address = SocketAddr::from((Ipv4Addr::LOCALHOST, server.port()));
+
+    let dashboard = Client::try_new_w_config(address, config(), String::from("dashboard"))
+        .await
+        .unwrap();
+    let mut subscription = dashboard
+        .subscribe_w_options(
+            &["/SmartDashboard/"],
+            Some(SubscriptionOptions {
+                prefix: Some(true),
+                ..Default::default()
+            }),
+        )
+        .await
+        .unwrap();
+    let sim = Client::try_new_w_config(address, config(), String::from("sim"))
+        .await
+        .unwrap();
+    let topic = sim
+        .publish_topic("/SmartDashboard/speed", Type::Double, None)
+        .await
+        .unwrap();
+    sim.publish_value(&topic, &rmpv::Value::F64(1.5)).await.unwrap();
+
+    let message = tokio::time::timeout(Duration::from_secs(5), subscription.next())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(message.topic_name, "/SmartDashboard/speed");
+    assert_eq!(message.data, rmpv::Value::F64(1.5));
+    assert_eq!(server.topic_names(), vec![String::from("/SmartDashboard/speed")]);
+    let mut clients = server.client_names();
+    clients.sort();
+    assert!(clients[0].starts_with("dashboard@127.0.0.1:"));
+    assert!(clients[1].starts_with("sim@127.0.0.1:"));
+    server.stop();
+}
+
+#[tokio::test]
+async fn test_nt4_server_set_properties() {
+    use crate::networktable::server::NetworkTableServer;
+    use crate::networktable::topics::TopicProperties;
+    use network_tables::v4::{Client, Config, SubscriptionOptions, Type};
+    use std::net::{Ipv4Addr, SocketAddr};
+    use std::time::Duration;
+
+    fn config() -> Config {
+        Config {
+            connect_timeout: 1000,
+            disconnect_retry_interval: 1000,
+            should_reconnect: Box::new(|_| false),
+            on_announce: Box::new(|_| Box::pin(async {})),
+            on_un_announce: Box::new(|_| Box::pin(async {})),
+            on_disconnect: Box::new(|| Box::pin(async {})),
+            on_reconnect: Box::new(|| Box::pin(async {})),
+        }
+    }
+
+    let server = NetworkTableServer::bind(0, &tokio::runtime::Handle::current()).unwrap();
+    let address = SocketAddr::from((Ipv4Addr::LOCALHOST, server.port()));
+    let dashboard = Client::try_new_w_config(address, config(), String::from("dashboard"))
+        .await
+        .unwrap();
+    let mut subscription = dashboard
+        .subscribe_w_options(&["/x"], Some(SubscriptionOptions::default()))
+        .await
+        .unwrap();
+    let sim = Client::try_new_w_config(address, config(), String::from("sim"))
+        .await
+        .unwrap();
+    let retained = TopicProperties {
+        retained: Some(true),
+        ..Default::default()
+    };
+
+    //the topic exists before it is made retained
+    let topic = sim.publish_topic("/x", Type::Int, None).await.unwrap();
+    sim.set_properties(&topic, retained.update()).await.unwrap();
+    //it keeps its publisher
+    sim.publish_value(&topic, &rmpv::Value::from(3)).await.unwrap();
+    let message = tokio::time::timeout(Duration::from_secs(5), subscription.next())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(message.data, rmpv::Value::from(3));
+    sim.unpublish(topic).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(server.topic_names(), vec![String::from("/x")]);
+
+    //dropping retained from a topic nobody publishes removes it
+    let topic = sim.publish_topic("/x", Type::Int, None).await.unwrap();
+    sim.set_properties(&topic, TopicProperties::default().update())
+        .await
+        .unwrap();
+    sim.unpublish(topic).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(server.topic_names().is_empty());
+    server.stop();
+}
+
+#[test]
+fn test_connection_policy() {
+    use crate::networktable::connection::ConnectionPolicy;
+    use std::time::Duration;
+
+    let policy: ConnectionPolicy = serde_json::from_value(serde_json::json!({
+        "retry_interval_ms": 250,
+        "retry_backoff": 2.0,
+        "max_retry_interval_ms": 1500,
+        "max_attempts": 3
+    }))
+    .unwrap();
+    assert!(policy.validate().is_ok());
+    //fields that are left out keep their defaults
+    assert_eq!(policy.connect_timeout_ms, ConnectionPolicy::default().connect_timeout_ms);
+    assert!(policy.reconnect);
+
+    assert_eq!(policy.retry_delay(1), Duration::from_millis(250));
+    assert_eq!(policy.retry_delay(2), Duration::from_millis(500));
+    assert_eq!(policy.retry_delay(3), Duration::from_millis(1000));
+    assert_eq!(policy.retry_delay(4), Duration::from_millis(1500));
+    assert_eq!(policy.retry_delay(u32::MAX), Duration::from_millis(1500));
+
+    assert!(policy.may_retry(2));
+    assert!(!policy.may_retry(3));
+    assert!(ConnectionPolicy::default().may_retry(u32::MAX));
+
+    let shrinking = ConnectionPolicy {
+        retry_backoff: 0.5,
+        ..Default::default()
+    };
+    assert!(shrinking.validate().is_err());
+    let default = ConnectionPolicy::default();
+    assert!(default.validate().is_ok());
+    assert!(default.max_retry_interval_ms > default.retry_interval_ms);
+    let capped_below = ConnectionPolicy {
+        retry_interval_ms: 2000,
+        max_retry_interval_ms: 1000,
+        ..Default::default()
+    };
+    assert!(capped_below.validate().is_err());
+    let no_timeout = ConnectionPolicy {
+        connect_timeout_ms: 0,
+        ..Default::default()
+    };
+    assert!(no_timeout.validate().is_err());
+}
+
+#[test]
+fn test_struct_schema() {
+    use crate::mushroom_types::{MushroomEntry, MushroomValue};
+    use crate::schema::structs::StructRegistry;
+    use crate::schema::{expand_entry, SchemaRegistry};
+    use crate::time::ServerTime;
+
+    let mut registry = StructRegistry::new();
+    registry.add("struct:Translation2d", "double x;double y").unwrap();
+    registry.add("struct:Rotation2d", "double value").unwrap();
+    registry
+        .add("struct:Pose2d", "Translation2d translation; Rotation2d rotation;")
+        .unwrap();
+    assert_eq!(registry.size("Pose2d"), Ok(24));
+
+    let raw: Vec<u8> = [1.5_f64, -2.0, 0.25]
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect();
+    assert_eq!(
+        registry.decode("struct:Pose2d", &raw),
+        Ok(serde_json::json!({
+            "translation": {"x": 1.5, "y": -2.0},
+            "rotation": {"value": 0.25}
+        }))
+    );
+    let both: Vec<u8> = raw.iter().chain(raw.iter()).copied().collect();
+    assert_eq!(
+        registry
+            .decode("struct:Pose2d[]", &both)
+            .unwrap()
+            .as_array()
+            .map(Vec::len),
+        Some(2)
+    );
+    assert!(registry.decode("struct:Pose2d", &raw[..20]).is_err());
+    assert!(registry.decode("struct:Missing", &raw).is_err());
+
+    //bit-fields of the same size share a unit while they fit, char arrays are strings
+    registry
+        .add(
+            "struct:Flags",
+            "enum {off=0, on=1} uint8 mode : 2; bool enabled : 1; int8 offset : 4; \
+             int16 wide; char name[4]; float scales[2]",
+        )
+        .unwrap();
+    assert_eq!(registry.size("Flags"), Ok(1 + 2 + 4 + 8));
+    let mut flags = vec![0b0111_0101];
+    flags.extend((-3_i16).to_le_bytes());
+    flags.extend(b"ab\0\0");
+    flags.extend(0.5_f32.to_le_bytes());
+    flags.extend(2.0_f32.to_le_bytes());
+    assert_eq!(
+        registry.decode("struct:Flags", &flags),
+        Ok(serde_json::json!({
+            "mode": "on",
+            "enabled": true,
+            "offset": -2,
+            "wide": -3,
+            "name": "ab",
+            "scales": [0.5, 2.0]
+        }))
+    );
+    //fields keep the order of the schema
+    let decoded = registry.decode("struct:Flags", &flags).unwrap();
+    let names: Vec<&String> = decoded.as_object().unwrap().keys().collect();
+    assert_eq!(names, ["mode", "enabled", "offset", "wide", "name", "scales"]);
+    //single chars are utf-8 like char arrays
+    registry.add("struct:Chars", "char a; char b").unwrap();
+    assert_eq!(
+        registry.decode("struct:Chars", &[b'A', 0xE9]),
+        Ok(serde_json::json!({"a": "A", "b": "\u{FFFD}"}))
+    );
+    assert!(registry.add("struct:Bad", "double x : 3").is_err());
+    assert!(registry.add("struct:Bad", "double").is_err());
+
+    //values of struct topics come with an entry per field
+    let mut registry = SchemaRegistry {
+        structs: registry,
+        ..Default::default()
+    };
+    let entries = expand_entry(
+        &mut registry,
+        MushroomEntry::new(
+            MushroomValue::ByteArray(raw.clone()),
+            "/robot/pose".into(),
+            Some(ServerTime(10)),
+        ),
+        Some("struct:Pose2d"),
+    );
+    assert!(matches!(entries[0].get_value(), MushroomValue::Struct(_)));
+    let json = serde_json::to_string(&entries[0].get_value()).unwrap();
+    assert!(json.find("translation") < json.find("rotation"));
+    let fields: Vec<(String, MushroomValue)> = entries[1..]
+        .iter()
+        .map(|entry| (String::from(entry.get_path()), entry.get_value()))
+        .collect();
+    assert_eq!(
+        fields,
+        vec![
+            (
+                String::from("/robot/pose/translation/x"),
+                MushroomValue::Double(1.5)
+            ),
+            (
+                String::from("/robot/pose/translation/y"),
+                MushroomValue::Double(-2.0)
+            ),
+            (
+                String::from("/robot/pose/rotation/value"),
+                MushroomValue::Double(0.25)
+            ),
+        ]
+    );
+
+    //field entries keep the declared types, NaN included
+    registry
+        .structs
+        .add(
+            "struct:Odometry",
+            "float heading; double wheels[2]; enum {idle=0} uint8 state",
+        )
+        .unwrap();
+    let mut odometry = 0.5_f32.to_le_bytes().to_vec();
+    odometry.extend(1.0_f64.to_le_bytes());
+    odometry.extend(f64::NAN.to_le_bytes());
+    odometry.push(3);
+    let entries = expand_entry(
+        &mut registry,
+        MushroomEntry::new(
+            MushroomValue::ByteArray(odometry),
+            "/robot/odometry".into(),
+            Some(ServerTime(10)),
+        ),
+        Some("struct:Odometry"),
+    );
+    assert_eq!(entries.len(), 4);
+    assert_eq!(String::from(entries[1].get_path()), "/robot/odometry/heading");
+    assert_eq!(entries[1].get_value(), MushroomValue::Float(0.5));
+    assert_eq!(String::from(entries[2].get_path()), "/robot/odometry/wheels");
+    match entries[2].get_value() {
+        MushroomValue::DoubleArray(wheels) => {
+            assert_eq!(wheels[0], 1.0);
+            assert!(wheels[1].is_nan());
+        }
+        other => panic!("wheels decoded as {:?}", other),
+    }
+    assert_eq!(entries[3].get_value(), MushroomValue::String(String::from("3")));
+
+    //schemas get registered from their topics
+    let mut registry = SchemaRegistry::new();
+    expand_entry(
+        &mut registry,
+        MushroomEntry::new(
+            MushroomValue::ByteArray(b"double value".to_vec()),
+            "/.schema/struct:Rotation2d".into(),
+            Some(ServerTime(0)),
+        ),
+        Some("structschema"),
+    );
+    assert!(registry.structs.get("Rotation2d").is_some());
+}
+
+#[test]
+fn test_protobuf_schema() {
+    use crate::mushroom_types::{MushroomEntry, MushroomValue};
+    use crate::schema::{expand_entry, SchemaRegistry};
+    use crate::time::ServerTime;
+
+    fn varint(mut value: u64, out: &mut Vec<u8>) {
+        while value >= 0x80 {
+            out.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+        out.push(value as u8);
+    }
+    fn field(number: u64, wire_type: u64, out: &mut Vec<u8>) {
+        varint(number << 3 | wire_type, out);
+    }
+    fn bytes(number: u64, value: &[u8], out: &mut Vec<u8>) {
+        field(number, 2, out);
+        varint(value.len() as u64, out);
+        out.extend_from_slice(value);
+    }
+    fn number(number: u64, value: u64, out: &mut Vec<u8>) {
+        field(number, 0, out);
+        varint(value, out);
+    }
+    //FieldDescriptorProto: name = 1, number = 3, label = 4, type = 5, type_name = 6
+    fn descriptor_field(name: &str, field_number: u64, repeated: bool, field_type: u64, type_name: &str) -> Vec<u8> {
+        let mut out = Vec::new();
+        bytes(1, name.as_bytes(), &mut out);
+        number(3, field_number, &mut out);
+        number(4, if repeated { 3 } else { 1 }, &mut out);
+        number(5, field_type, &mut out);
+        if !type_name.is_empty() {
+            bytes(6, type_name.as_bytes(), &mut out);
+        }
+        out
+    }
+
+    let mut translation = Vec::new();
+    bytes(1, b"ProtobufTranslation2d", &mut translation);
+    bytes(2, &descriptor_field("x", 1, false, 1, ""), &mut translation);
+    bytes(2, &descriptor_field("y", 2, false, 1, ""), &mut translation);
+    let mut pose = Vec::new();
+    bytes(1, b"ProtobufPose2d", &mut pose);
+    bytes(2, &descriptor_field("translation", 1, false, 11, ".wpi.proto.ProtobufTranslation2d"), &mut pose);
+    bytes(2, &descriptor_field("mode", 2, false, 14, ".wpi.proto.Mode"), &mut pose);
+    bytes(2, &descriptor_field("ids", 3, true, 5, ""), &mut pose);
+    bytes(2, &descriptor_field("delta", 4, false, 18, ""), &mut pose);
+    let mut mode = Vec::new();
+    bytes(1, b"Mode", &mut mode);
+    for (name, value) in [("OFF", 0), ("ON", 1)] {
+        let mut enum_value = Vec::new();
+        bytes(1, name.as_bytes(), &mut enum_value);
+        number(2, value, &mut enum_value);
+        bytes(2, &enum_value, &mut mode);
+    }
+    let mut file = Vec::new();
+    bytes(1, b"geometry2d.proto", &mut file);
+    bytes(2, b"wpi.proto", &mut file);
+    bytes(4, &pose, &mut file);
+    bytes(4, &translation, &mut file);
+    bytes(5, &mode, &mut file);
+
+    //y is left out like proto3 does for zero values, ids are packed
+    let mut translation_value = Vec::new();
+    field(1, 1, &mut translation_value);
+    translation_value.extend(1.5_f64.to_le_bytes());
+    let mut packed_ids = Vec::new();
+    varint(1, &mut packed_ids);
+    varint(300, &mut packed_ids);
+    let mut raw = Vec::new();
+    bytes(1, &translation_value, &mut raw);
+    number(2, 1, &mut raw);
+    bytes(3, &packed_ids, &mut raw);
+    number(4, 5, &mut raw);
+
+    let mut registry = SchemaRegistry::new();
+    expand_entry(
+        &mut registry,
+        MushroomEntry::new(
+            MushroomValue::ByteArray(file),
+            "NT:/.schema/proto:geometry2d.proto".into(),
+            Some(ServerTime(0)),
+        ),
+        Some("proto:FileDescriptorProto"),
+    );
+    assert_eq!(
+        registry.protobufs.decode("proto:wpi.proto.ProtobufPose2d", &raw),
+        Ok(serde_json::json!({
+            "translation": {"x": 1.5, "y": 0.0},
+            "mode": "ON",
+            "ids": [1, 300],
+            "delta": -3
+        }))
+    );
+    assert!(registry
+        .protobufs
+        .decode("proto:wpi.proto.ProtobufPose2d", &raw[..raw.len() - 1])
+        .is_err());
+
+    let entries = expand_entry(
+        &mut registry,
+        MushroomEntry::new(
+            MushroomValue::ByteArray(raw),
+            "/robot/pose".into(),
+            Some(ServerTime(1)),
+        ),
+        Some("proto:wpi.proto.ProtobufPose2d"),
+    );
+    assert!(matches!(entries[0].get_value(), MushroomValue::Struct(_)));
+    assert!(entries.iter().any(|entry| {
+        String::from(entry.get_path()) == "/robot/pose/ids"
+            && entry.get_value() == MushroomValue::IntArray(vec![1, 300])
+    }));
+}
+
+#[test]
+fn test_value_decode() {
+    use crate::mushroom_types::MushroomValue;
+
+    fn decode(type_name: &str, value: rmpv::Value) -> Result<MushroomValue, String> {
+        MushroomValue::try_from((type_name, value))
+    }
+
+    //empty arrays get the type of their topic
+    assert_eq!(
+        decode("string[]", rmpv::Value::Array(Vec::new())),
+        Ok(MushroomValue::StringArray(Vec::new()))
+    );
+    assert_eq!(
+        decode("int[]", rmpv::Value::Array(vec![1.into(), 2.into()])),
+        Ok(MushroomValue::IntArray(vec![1, 2]))
+    );
+    //quotes are part of the string
+    assert_eq!(
+        decode("string", "say \"hi\"".into()),
+        Ok(MushroomValue::String(String::from("say \"hi\"")))
+    );
+    assert_eq!(decode("double", 2.into()), Ok(MushroomValue::Double(2.0)));
+    assert_eq!(
+        decode("struct:Pose2d", rmpv::Value::Binary(vec![1, 2])),
+        Ok(MushroomValue::ByteArray(vec![1, 2]))
+    );
+
+    assert!(decode("int", u64::MAX.into()).is_err());
+    assert!(decode("boolean", rmpv::Value::Nil).is_err());
+    assert!(decode("double[]", rmpv::Value::Array(vec![1.0.into(), "a".into()])).is_err());
+    assert!(decode("raw", rmpv::Value::Map(Vec::new())).is_err());
+    assert!(matches!(
+        decode("msgpack", rmpv::Value::Map(Vec::new())),
+        Ok(MushroomValue::ByteArray(_))
+    ));
+}
+
+#[test]
+fn test_value_serde() {
+    use crate::mushroom_types::{MushroomEntry, MushroomTable, MushroomValue};
+    use crate::schema::StructValue;
+    use crate::time::ServerTime;
+
+    let values = vec![
+        MushroomValue::Float(0.5),
+        MushroomValue::Double(0.5),
+        MushroomValue::ByteArray(vec![1, 2]),
+        MushroomValue::Protobuf(vec![1, 2]),
+        MushroomValue::IntArray(vec![-1, 2]),
+        MushroomValue::Struct(StructValue {
+            type_name: String::from("struct:Translation2d"),
+            fields: serde_json::json!({"x": 1.0, "y": 2.0}),
+            raw: vec![0; 16],
+        }),
+    ];
+    for value in values {
+        let json = serde_json::to_string(&value).unwrap();
+        assert_eq!(serde_json::from_str::<MushroomValue>(&json).unwrap(), value);
+    }
+    assert!(serde_json::from_str::<MushroomValue>(r#"{"type": "Int", "value": "1"}"#).is_err());
+    assert!(serde_json::from_str::<MushroomValue>(r#"{"type": "Struct", "value": {}}"#).is_err());
+
+    //NaN and the infinities survive json as strings and msgpack as floats
+    let values = vec![
+        MushroomValue::Double(f64::NAN),
+        MushroomValue::Float(f64::INFINITY),
+        MushroomValue::Double(f64::NEG_INFINITY),
+        MushroomValue::DoubleArray(vec![1.0, f64::NAN, f64::INFINITY]),
+        MushroomValue::FloatArray(vec![f64::NEG_INFINITY, 0.5]),
+    ];
+    for value in values {
+        let json = serde_json::to_string(&value).unwrap();
+        let read = serde_json::from_str::<MushroomValue>(&json).unwrap();
+        //NaN isn't equal to itself
+        assert_eq!(read.to_string(), value.to_string());
+        let msgpack = rmp_serde::to_vec(&value).unwrap();
+        let read = rmp_serde::from_slice::<MushroomValue>(&msgpack).unwrap();
+        assert_eq!(read.to_string(), value.to_string());
+    }
+    assert_eq!(
+        serde_json::to_string(&MushroomValue::Double(f64::NAN)).unwrap(),
+        r#"{"type":"Double","value":"NaN"}"#
+    );
+    assert!(serde_json::from_str::<MushroomValue>(r#"{"type": "Double", "value": "nan"}"#).is_err());
+
+    let mut table = MushroomTable::new(ServerTime(1234));
+    table.add_entry(MushroomEntry::new(MushroomValue::Float(1.0), "/a".into(), Some(ServerTime(2))));
+    table.add_entry(MushroomEntry::new(MushroomValue::Boolean(true), "/b".into(), None));
+    let json = serde_json::to_string(&table).unwrap();
+    //tables are sent to the frontend as a plain entry array
+    assert!(json.starts_with('['));
+    let read: MushroomTable = serde_json::from_str(&json).unwrap();
+    assert_eq!(read.get_timestamp(), ServerTime(2));
+    assert_eq!(read.get_entries(), table.get_entries());
+    assert_eq!(read.get_entry(&"/b".into()), table.get_entry(&"/b".into()));
+}
+
+#[test]
+fn test_table_path_queries() {
+    use crate::mushroom_types::{MushroomEntry, MushroomPath, MushroomTable, MushroomValue};
+    use crate::time::ServerTime;
+
+    let mut table = MushroomTable::new(ServerTime(0));
+    for path in [
+        "/SmartDashboard/left/velocity",
+        "/SmartDashboard/right/velocity",
+        "/SmartDashboard/right/position",
+        "/Shuffleboard/Drive",
+        "/Shuffleboard/Drive/speed",
+        "/Shuffleboard/Drive2/speed",
+    ] {
+        table.add_entry(MushroomEntry::new(MushroomValue::Int(1), path.into(), None));
+    }
+    let paths = |table: MushroomTable| -> Vec<String> {
+        let mut paths: Vec<String> = table
+            .get_entries()
+            .iter()
+            .map(|entry| entry.get_path().into())
+            .collect();
+        paths.sort();
+        paths
+    };
+
+    assert_eq!(
+        paths(table.entries_under(&"/Shuffleboard/Drive/".into())),
+        vec!["/Shuffleboard/Drive", "/Shuffleboard/Drive/speed"]
+    );
+    assert_eq!(table.entries_under(&"/".into()).len(), 6);
+    assert_eq!(
+        paths(table.entries_matching(&"/SmartDashboard/*/velocity".into())),
+        vec!["/SmartDashboard/left/velocity", "/SmartDashboard/right/velocity"]
+    );
+    assert_eq!(table.entries_matching(&"/**/speed".into()).len(), 2);
+    assert_eq!(table.entries_matching(&"/Shuffleboard/**".into()).len(), 3);
+    assert!(table.entries_matching(&"/*/velocity".into()).is_empty());
+    assert_eq!(table.children(&"/".into()), vec!["Shuffleboard", "SmartDashboard"]);
+    assert_eq!(table.children(&"/SmartDashboard/right".into()), vec!["position", "velocity"]);
+    assert!(table.children(&"/Shuffleboard/Drive/speed".into()).is_empty());
+
+    let tree = table.tree(&MushroomPath::from("/Shuffleboard"));
+    let drive = tree.get(&["Drive"]).unwrap();
+    assert!(drive.entry.is_some());
+    assert!(drive.children["speed"].entry.is_some());
+    assert!(tree.get(&["Drive2", "speed"]).is_some());
+    assert!(tree.entry.is_none());
+    assert_eq!(tree.children.len(), 2);
+}
+
+#[test]
+fn test_table_delta() {
+    use crate::mushroom_types::{MushroomEntry, MushroomPath, MushroomTable, MushroomValue};
+    use crate::time::ServerTime;
+
+    let entry =
+        |path: &str, value: i64| MushroomEntry::new(MushroomValue::Int(value), path.into(), None);
+    let mut table = MushroomTable::new(ServerTime(0));
+    let epoch = table.get_epoch();
+    table.add_entry(entry("/a", 1));
+    table.add_entry(entry("/b", 1));
+    let first = table.delta_since(epoch, 0);
+    assert_eq!(first.entries.len(), 2);
+    assert!(!first.reset);
+
+    //the same value again is not a change
+    table.add_entry(entry("/a", 1));
+    assert_eq!(table.get_version(), first.version);
+    assert!(table.delta_since(epoch, first.version).entries.is_empty());
+
+    table.add_entry(entry("/b", 2));
+    table.remove_entry(&"/a".into());
+    let second = table.delta_since(epoch, first.version);
+    assert_eq!(second.entries, vec![entry("/b", 2)]);
+    assert_eq!(second.removed, vec![MushroomPath::from("/a")]);
+    assert!(table.delta_since(epoch, second.version).entries.is_empty());
+
+    //coming back clears the removal
+    table.add_entry(entry("/a", 3));
+    let third = table.delta_since(epoch, second.version);
+    assert_eq!(third.entries, vec![entry("/a", 3)]);
+    assert!(table.delta_since(epoch, 0).removed.is_empty());
+
+    //a replacing table is told apart by its epoch even once it has more versions
+    let mut replaced = MushroomTable::new(ServerTime(0));
+    assert_ne!(replaced.get_epoch(), epoch);
+    for i in 0..10 {
+        replaced.add_entry(entry("/c", i));
+    }
+    let reset = replaced.delta_since(epoch, third.version);
+    assert!(reset.reset);
+    assert_eq!(reset.entries, vec![entry("/c", 9)]);
+    assert_eq!(reset.epoch, replaced.get_epoch());
+    let reset = MushroomTable::new(ServerTime(0)).delta_since(epoch, third.version);
+    assert!(reset.reset);
+    assert!(reset.entries.is_empty());
+
+    //only the newest removals are kept, older deltas start over
+    let mut table = MushroomTable::new(ServerTime(0));
+    let epoch = table.get_epoch();
+    for i in 0..5000 {
+        let path = format!("/removed/{}", i);
+        table.add_entry(entry(&path, i));
+        table.remove_entry(&path.as_str().into());
+    }
+    let delta = table.delta_since(epoch, table.get_version() - 2);
+    assert!(!delta.reset);
+    assert_eq!(delta.removed, vec![MushroomPath::from("/removed/4999")]);
+    assert!(table.delta_since(epoch, 1).reset);
+}
+
+#[test]
+fn test_ipc_query() {
+    use crate::mushroom_types::{MushroomEntry, MushroomTable, MushroomValue};
+    use crate::networktable::handler::NetworkTableClientId;
+    use crate::networktable::ipc::{allowed_origin, IpcFormat, IpcQuery};
+    use crate::time::ServerTime;
+
+    let query = IpcQuery::parse(
+        "https://enoki.localhost/delta?client=%7B%22host%22%3A%2210.0.0.2%22%2C%22port%22%3A5810%2C%22identity%22%3A%22Enoki+1%22%7D&since=3&format=msgpack",
+    )
+    .unwrap();
+    assert_eq!(query.route, "delta");
+    assert_eq!(query.params["since"], "3");
+    assert_eq!(query.format(), Ok(IpcFormat::MessagePack));
+    assert_eq!(
+        query.client_id(),
+        Ok(NetworkTableClientId::new(
+            String::from("10.0.0.2"),
+            5810,
+            String::from("Enoki 1")
+        ))
+    );
+    let parse = |uri: &str| IpcQuery::parse(uri).unwrap();
+    assert_eq!(parse("enoki://localhost/table/").route, "table");
+    assert_eq!(parse("enoki://localhost/table").format(), Ok(IpcFormat::Json));
+    assert!(parse("enoki://localhost/table?format=xml").format().is_err());
+    assert!(parse("enoki://localhost/table").client_id().is_err());
+    assert_eq!(parse("enoki://localhost/table?a=%E2%82%AC%2B").params["a"], "€+");
+    assert!(IpcQuery::parse("not a uri").is_err());
+
+    //only the app itself may read the responses
+    assert_eq!(allowed_origin(Some("tauri://localhost")), Some("tauri://localhost"));
+    assert_eq!(allowed_origin(Some("https://example.com")), None);
+    assert_eq!(allowed_origin(None), None);
+
+    let mut table = MushroomTable::new(ServerTime(1_700_000_000_000_000));
+    table.add_entry(MushroomEntry::new(
+        MushroomValue::DoubleArray((0..64).map(|i| (i as f64).sin()).collect()),
+        "/odometry".into(),
+        Some(ServerTime(1)),
+    ));
+    table.add_entry(MushroomEntry::new(MushroomValue::Float(0.5), "/speed".into(), None));
+    let json = IpcFormat::Json.encode(&table).unwrap();
+    let msgpack = IpcFormat::MessagePack.encode(&table).unwrap();
+    assert!(msgpack.len() < json.len());
+    let read: MushroomTable = rmp_serde::from_slice(&msgpack).unwrap();
+    assert_eq!(read.get_timestamp(), ServerTime(1));
+    assert_eq!(read.get_entries(), table.get_entries());
+
+    //bytes go out as one msgpack bin blob
+    let bytes = MushroomValue::Protobuf(vec![0xff; 32]);
+    let msgpack = IpcFormat::MessagePack.encode(&bytes).unwrap();
+    assert!(msgpack.windows(2).any(|header| header == [0xc4, 32]));
+    assert!(msgpack.len() < 64);
+    assert_eq!(rmp_serde::from_slice::<MushroomValue>(&msgpack).unwrap(), bytes);
+    //json still has them as an array of numbers
+    assert_eq!(
+        IpcFormat::Json.encode(&MushroomValue::ByteArray(vec![1, 2])).unwrap(),
+        br#"{"type":"ByteArray","value":[1,2]}"#
+    );
+}
+
+#[test]
+fn test_time_conversion() {
+    use crate::time::{MonotonicTime, ServerClock, ServerTime, WallTime};
+
+    crate::time::init();
+    let before = MonotonicTime::now();
+    std::thread::sleep(std::time::Duration::from_millis(2));
+    assert!(MonotonicTime::now().0 >= before.0 + 2000);
+
+    let clock = ServerClock { offset: 5_000_000 };
+    assert_eq!(clock.to_server(MonotonicTime(1_000)), ServerTime(5_001_000));
+    assert_eq!(clock.to_monotonic(ServerTime(5_001_000)), MonotonicTime(1_000));
+    //a server time from before the app started
+    assert_eq!(clock.to_monotonic(ServerTime(0)), MonotonicTime(0));
+    let measured = ServerClock::measure(clock.server_now());
+    assert!((measured.offset - clock.offset).abs() < 1_000);
+
+    let now = MonotonicTime::now();
+    assert_eq!(now.to_wall().to_monotonic(), now);
+    //the clocks keep their first start
+    crate::time::init();
+    assert!(MonotonicTime::now() >= now);
+    let wall = WallTime::now().0 as i64;
+    assert!((now.to_wall().0 as i64 - wall).abs() < 1_000_000);
+}
+
+#[test]
+fn test_datalog_replay() {
+    use crate::datalog::replay::{
+        PlaybackCommand, PlaybackStatus, ReplayState, Sample, MAX_REPLAY_SPEED,
+    };
+    use crate::mushroom_types::{MushroomEntry, MushroomValue};
+    use crate::networktable::handler::{
+        NetworkTableClientId, NetworkTableUpdate, SubscriptionPackage, SubscriptionRequest,
+    };
+    use crate::networktable::history::{HistoryConfig, TopicHistory};
+    use crate::time::ServerTime;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    let id = NetworkTableClientId::new_for_replay(String::from("logs/match.wpilog"));
+    assert_eq!(id.repr(), "replay:logs/match.wpilog");
+    assert_ne!(
+        id,
+        NetworkTableClientId::new(String::from("logs/match.wpilog"), 0, String::from("replay"))
+    );
+
+    let sample = |topic: &str, value: MushroomValue, time: u64| Sample {
+        topic: String::from(topic),
+        entry: MushroomEntry::new(value, topic.into(), Some(ServerTime(time))),
+    };
+    let samples = vec![
+        sample("/a", MushroomValue::Int(1), 1_000_000),
+        sample("/b", MushroomValue::Boolean(true), 1_000_000),
+        sample("/a", MushroomValue::Int(2), 2_000_000),
+        sample("/a", MushroomValue::Int(3), 3_000_000),
+    ];
+    let status = Arc::new(Mutex::new(PlaybackStatus {
+        playing: false,
+        speed: 1.0,
+        position: 1_000_000,
+        start: 1_000_000,
+        end: 3_000_000,
+    }));
+    let updates: Arc<Mutex<Vec<NetworkTableUpdate>>> = Arc::default();
+    let sent = updates.clone();
+    let mut state = ReplayState::new(
+        id,
+        TopicHistory::new(HistoryConfig::default()),
+        samples,
+        status,
+        move |update| sent.lock().unwrap().push(update),
+    );
+    //the values of the newest update at /a
+    let last = || {
+        let update = updates.lock().unwrap().pop();
+        update.map(|update| {
+            (
+                update
+                    .entries()
+                    .get_entries()
+                    .iter()
+                    .map(MushroomEntry::get_value)
+                    .collect::<Vec<_>>(),
+                update.removed().len(),
+            )
+        })
+    };
+
+    //subscribing sends the table at the position
+    state.subscription(SubscriptionRequest::Subscribe(vec![SubscriptionPackage::new(
+        String::from("/a"),
+        Default::default(),
+    )]));
+    assert_eq!(last(), Some((vec![MushroomValue::Int(1)], 0)));
+
+    //paused replays don't move
+    state.play(Duration::from_secs(1));
+    assert_eq!(last(), None);
+
+    state.command(PlaybackCommand::Play);
+    assert!(state.status().playing);
+    state.play(Duration::from_secs(1));
+    assert_eq!(state.status().position, 2_000_000);
+    assert_eq!(last(), Some((vec![MushroomValue::Int(2)], 0)));
+
+    //the end of the log stops playing
+    state.command(PlaybackCommand::Speed(4.0));
+    state.play(Duration::from_secs(1));
+    assert_eq!(state.status().position, 3_000_000);
+    assert!(!state.status().playing);
+    assert_eq!(last(), Some((vec![MushroomValue::Int(3)], 0)));
+
+    //playing again starts over
+    state.command(PlaybackCommand::Play);
+    assert_eq!(state.status().position, 1_000_000);
+    assert_eq!(last(), Some((vec![MushroomValue::Int(1)], 0)));
+    state.command(PlaybackCommand::Pause);
+    assert!(!state.status().playing);
+
+    state.command(PlaybackCommand::Seek(2_500_000));
+    assert_eq!(last(), Some((vec![MushroomValue::Int(2)], 0)));
+    state.command(PlaybackCommand::Seek(10_000_000));
+    assert_eq!(state.status().position, 3_000_000);
+
+    state.subscription(SubscriptionRequest::Unsubscribe(vec![String::from("/a")]));
+    assert_eq!(last(), Some((Vec::new(), 1)));
+
+    //a position near the end of the clock doesn't overflow at full speed
+    let status = Arc::new(Mutex::new(PlaybackStatus {
+        playing: false,
+        speed: MAX_REPLAY_SPEED,
+        position: u64::MAX - 10,
+        start: 0,
+        end: u64::MAX,
+    }));
+    let mut state = ReplayState::new(
+        NetworkTableClientId::new_for_replay(String::from("logs/long.wpilog")),
+        TopicHistory::new(HistoryConfig::default()),
+        Vec::new(),
+        status,
+        |_| {},
+    );
+    state.command(PlaybackCommand::Play);
+    state.play(Duration::from_secs(3600));
+    assert_eq!(state.status().position, u64::MAX);
+    assert!(!state.status().playing);
+}
+
+#[test]
+fn test_flush_timer() {
+    use crate::networktable::handler::FlushTimer;
+    use std::time::Duration;
+    use tokio::time::Instant;
+
+    let now = Instant::now();
+    let interval = Duration::from_millis(15);
+    let mut timer = FlushTimer::default();
+    assert_eq!(timer.at(), None);
+    //values coming in during the interval don't push the flush back
+    assert!(!timer.received(interval, now));
+    assert!(!timer.received(interval, now + Duration::from_millis(10)));
+    assert_eq!(timer.at(), Some(now + interval));
+    timer.flushed();
+    assert_eq!(timer.at(), None);
+    assert!(!timer.received(interval, now + Duration::from_millis(20)));
+    assert_eq!(timer.at(), Some(now + Duration::from_millis(35)));
+    timer.flushed();
+
+    //without coalescing every value is flushed right away
+    assert!(timer.received(Duration::ZERO, now));
+    assert_eq!(timer.at(), None);
+}
@@ -0,0 +1,101 @@
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::OnceCell;
+
+/// Microseconds on the clock of the nt4 server a client is connected to,
+/// for a datalog replay the server is the log so it is the time of the log
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+pub struct ServerTime(pub u64);
+
+/// Microseconds since the app started, never goes backwards,
+/// datalog records are written with it
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+pub struct MonotonicTime(pub u64);
+
+/// Microseconds since the unix epoch
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+pub struct WallTime(pub u64);
+
+/// When the app started on both local clocks
+static START: OnceCell<(Instant, WallTime)> = OnceCell::const_new();
+
+/// Starts the local clocks with the app, otherwise the first time read starts them.
+/// Later calls keep the first start
+pub fn init() {
+    START.set((Instant::now(), WallTime::now())).ok();
+}
+
+fn start() -> (Instant, WallTime) {
+    loop {
+        if let Some(start) = START.get() {
+            return *start;
+        }
+        //a thread starting them at the same time is done right after
+        init();
+    }
+}
+
+impl ServerTime {
+    pub fn as_secs(&self) -> f64 {
+        self.0 as f64 / 1_000_000_f64
+    }
+}
+
+impl MonotonicTime {
+    pub fn now() -> Self {
+        MonotonicTime(start().0.elapsed().as_micros() as u64)
+    }
+
+    pub fn to_wall(self) -> WallTime {
+        WallTime(start().1 .0 + self.0)
+    }
+}
+
+impl WallTime {
+    pub fn now() -> Self {
+        WallTime(chrono::Utc::now().timestamp_micros().max(0) as u64)
+    }
+
+    /// Times from before the app started are clamped to its start
+    pub fn to_monotonic(self) -> MonotonicTime {
+        MonotonicTime(self.0.saturating_sub(start().1 .0))
+    }
+}
+
+/// How far a server clock is ahead of the local monotonic one, converts between the two
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerClock {
+    /// Microseconds, negative when the server started after the app
+    pub offset: i64,
+}
+
+impl ServerClock {
+    /// `server_now` has to be read right before this
+    pub fn measure(server_now: ServerTime) -> Self {
+        Self {
+            offset: server_now.0 as i64 - MonotonicTime::now().0 as i64,
+        }
+    }
+
+    pub fn to_server(&self, time: MonotonicTime) -> ServerTime {
+        ServerTime((time.0 as i64 + self.offset).max(0) as u64)
+    }
+
+    pub fn to_monotonic(&self, time: ServerTime) -> MonotonicTime {
+        MonotonicTime((time.0 as i64 - self.offset).max(0) as u64)
+    }
+
+    pub fn server_now(&self) -> ServerTime {
+        self.to_server(MonotonicTime::now())
+    }
+}